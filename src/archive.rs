@@ -0,0 +1,136 @@
+// Libraries of object files, from which the linker takes only the members defining
+// a symbol that is still undefined. The archive starts with an index of the
+// global symbols of each member, followed by the members themselves, each with the
+// number of lines of its object:
+//
+//   MINI-RISC archive 1
+//   index div div.o
+//   index bcd bcd.o
+//   member div.o 12
+//   MINI-RISC object 1
+//   ...
+use std::collections::HashMap;
+
+use crate::{
+    object::{Binding, Object},
+    Diagnostic,
+};
+
+const HEADER: &str = "MINI-RISC archive 1";
+
+pub struct Archive {
+    pub members: Vec<(String, Object)>,
+    // the member defining each global symbol
+    pub index: HashMap<String, usize>,
+}
+
+impl Archive {
+    // name is the archive file, for errors
+    pub fn new(name: &str, members: Vec<(String, Object)>) -> Result<Self, Diagnostic> {
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for (i, (member, object)) in members.iter().enumerate() {
+            for symbol in &object.symbols {
+                if symbol.binding != Binding::Global {
+                    continue;
+                }
+                if let Some(first) = index.insert(symbol.name.clone(), i) {
+                    return Err(Diagnostic::new(format!(
+                        "ERR: {name}: {} is defined .global in both {} and {member}",
+                        symbol.name, members[first].0
+                    )));
+                }
+            }
+        }
+        Ok(Archive { members, index })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![HEADER.to_string()];
+        let mut index: Vec<(&String, &usize)> = self.index.iter().collect();
+        index.sort_by_key(|(name, member)| (**member, *name));
+        for (name, member) in index {
+            lines.push(format!("index {name} {}", self.members[*member].0));
+        }
+        let mut text = lines.join("\n") + "\n";
+        for (member, object) in &self.members {
+            let object = object.to_text();
+            text += &format!("member {member} {}\n{object}", object.lines().count());
+        }
+        text
+    }
+
+    pub fn from_text(name: &str, text: &str) -> Result<Self, Diagnostic> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(HEADER) {
+            return Err(Diagnostic::new(format!("ERR: {name} is not an archive")));
+        }
+        let mut index: Vec<(String, String)> = Vec::new();
+        let mut members = Vec::new();
+        while let Some((i, line)) = lines.next() {
+            let invalid = || {
+                Diagnostic::new(format!(
+                    "ERR: {name}: line {}: invalid archive line: {line}",
+                    i + 1
+                ))
+                .at(name, i + 1)
+            };
+            let (kind, rest) = line.split_once(' ').ok_or_else(invalid)?;
+            match kind {
+                "index" => {
+                    let (symbol, member) = rest.split_once(' ').ok_or_else(invalid)?;
+                    index.push((symbol.to_string(), member.to_string()));
+                }
+                "member" => {
+                    let (member, count) = rest.rsplit_once(' ').ok_or_else(invalid)?;
+                    let count: usize = count.parse().map_err(|_| invalid())?;
+                    let object: Vec<&str> =
+                        lines.by_ref().take(count).map(|(_, line)| line).collect();
+                    if object.len() != count {
+                        return Err(Diagnostic::new(format!(
+                            "ERR: {name}: member {member} ends early"
+                        )));
+                    }
+                    let object =
+                        Object::from_text(&format!("{name}({member})"), &object.join("\n"))?;
+                    members.push((member.to_string(), object));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        let archive = Archive::new(name, members)?;
+        // the index is rebuilt from the members, so it must agree with them
+        for (symbol, member) in index {
+            let found = archive.index.get(&symbol).map(|i| &archive.members[*i].0);
+            if found != Some(&member) {
+                return Err(Diagnostic::new(format!(
+                    "ERR: {name}: the index gives {symbol} in {member}, which does not define it"
+                )));
+            }
+        }
+        Ok(archive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_archives_are_errors() {
+        let error = |text: &str| match Archive::from_text("math.a", text) {
+            Ok(_) => panic!("expected an error for {text:?}"),
+            Err(err) => err.message,
+        };
+        assert_eq!(error("hello\n"), "ERR: math.a is not an archive");
+        assert!(error(&format!("{HEADER}\nindex div\n")).starts_with("ERR: math.a: line 2: "));
+        assert_eq!(
+            error(&format!("{HEADER}\nmember div.o 3\nMINI-RISC object 1\n")),
+            "ERR: math.a: member div.o ends early"
+        );
+        assert_eq!(
+            error(&format!("{HEADER}\nmember div.o 1\nhello\n")),
+            "ERR: math.a(div.o) is not an object file"
+        );
+        assert!(error(&format!("{HEADER}\nindex div div.o\n")).contains("does not define it"));
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    instruction::Op,
+    preprocess::{Location, Sources},
+    program::Program,
+    sim::{Machine, Status},
+};
+
+pub struct Coverage {
+    pub status: Status,
+    // executions of each instruction address
+    pub hits: Vec<u64>,
+    // for JF instructions, how often the branch was taken and not taken
+    pub taken: Vec<u64>,
+    pub not_taken: Vec<u64>,
+}
+
+#[derive(Default)]
+struct LineCoverage {
+    hits: u64,
+    // (taken, not taken) for each JF on the line
+    branches: Vec<(u64, u64)>,
+}
+
+pub fn coverage(program: &Program, max_steps: u64) -> Coverage {
+    let len = program.instructions.len();
+    let (mut hits, mut taken, mut not_taken) = (vec![0; len], vec![0; len], vec![0; len]);
+    let mut machine = Machine::load(program);
+    machine.run(program, max_steps, |address, machine| {
+        let address = address as usize;
+        hits[address] += 1;
+        if let Op::JF(_) = program.instructions[address] {
+            if machine.pc as usize == address + 1 {
+                not_taken[address] += 1;
+            } else {
+                taken[address] += 1;
+            }
+        }
+    });
+    Coverage {
+        status: machine.status,
+        hits,
+        taken,
+        not_taken,
+    }
+}
+
+impl Coverage {
+    fn per_line(&self, program: &Program) -> BTreeMap<Location, LineCoverage> {
+        let mut lines: BTreeMap<Location, LineCoverage> = BTreeMap::new();
+        for (address, &line) in program.lines.iter().enumerate() {
+            if program.is_padding(address) {
+                continue;
+            }
+            let entry = lines.entry(line).or_default();
+            // a line is run as many times as its first instruction is
+            if entry.hits == 0 {
+                entry.hits = self.hits[address];
+            }
+            if let Op::JF(_) = program.instructions[address] {
+                entry
+                    .branches
+                    .push((self.taken[address], self.not_taken[address]));
+            }
+        }
+        lines
+    }
+
+    pub fn print_text(&self, sources: &Sources, program: &Program) {
+        let lines = self.per_line(program);
+        let lines_hit = lines.values().filter(|line| line.hits > 0).count();
+        let branches: Vec<_> = lines.values().flat_map(|line| &line.branches).collect();
+        let branches_hit: usize = branches
+            .iter()
+            .map(|(taken, not_taken)| (*taken > 0) as usize + (*not_taken > 0) as usize)
+            .sum();
+        let percent = |hit: usize, total: usize| hit as f64 * 100.0 / total.max(1) as f64;
+
+        if self.status == Status::Running {
+            println!("warning: program stopped at the step limit before halting");
+        }
+        println!(
+            "lines:    {lines_hit}/{} ({:.1}%)",
+            lines.len(),
+            percent(lines_hit, lines.len())
+        );
+        println!(
+            "branches: {branches_hit}/{} ({:.1}%)\n",
+            branches.len() * 2,
+            percent(branches_hit, branches.len() * 2)
+        );
+        for (file, source) in sources.files.iter().enumerate() {
+            if file > 0 {
+                println!("\n{:16} | ; {}", "", source.name);
+            }
+            for (i, text) in source.lines.iter().enumerate() {
+                let line = i + 1;
+                let (count, branches) = match lines.get(&Location { file, line }) {
+                    Some(line) if line.hits == 0 => ("#####".to_string(), String::new()),
+                    Some(line) => (
+                        line.hits.to_string(),
+                        line.branches
+                            .iter()
+                            .map(|(taken, not_taken)| {
+                                format!("  [taken {taken}, not taken {not_taken}]")
+                            })
+                            .collect(),
+                    ),
+                    None => ("-".to_string(), String::new()),
+                };
+                println!("{count:>10} {line:>5} | {text}{branches}");
+            }
+        }
+    }
+
+    pub fn print_lcov(&self, sources: &Sources, program: &Program) {
+        let lines = self.per_line(program);
+        println!("TN:");
+        // a record for each file
+        for (file, source) in sources.files.iter().enumerate() {
+            let lines: Vec<_> = lines
+                .iter()
+                .filter(|(location, _)| location.file == file)
+                .map(|(location, coverage)| (location.line, coverage))
+                .collect();
+            println!("SF:{}", source.name);
+            let (mut branches, mut branches_hit) = (0, 0);
+            for (line, coverage) in &lines {
+                for (block, (taken, not_taken)) in coverage.branches.iter().enumerate() {
+                    for (branch, count) in [taken, not_taken].into_iter().enumerate() {
+                        // lcov uses `-` for branches on lines that never ran
+                        let count = match coverage.hits {
+                            0 => "-".to_string(),
+                            _ => count.to_string(),
+                        };
+                        println!("BRDA:{line},{block},{branch},{count}");
+                    }
+                    branches += 2;
+                    branches_hit += (*taken > 0) as usize + (*not_taken > 0) as usize;
+                }
+            }
+            println!("BRF:{branches}");
+            println!("BRH:{branches_hit}");
+            for (line, coverage) in &lines {
+                println!("DA:{line},{}", coverage.hits);
+            }
+            println!("LF:{}", lines.len());
+            println!(
+                "LH:{}",
+                lines.iter().filter(|(_, line)| line.hits > 0).count()
+            );
+            println!("end_of_record");
+        }
+    }
+}
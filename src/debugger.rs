@@ -0,0 +1,311 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    parse_file::parse_number,
+    preprocess::{Location, Sources},
+    program::Program,
+    sim::{Machine, Status},
+    types::{BitPos, Reg, FLAG_NAMES},
+};
+
+const HELP: &str = "\
+commands:
+  s, step [n]          execute n instructions (default 1)
+  c, continue          run until a breakpoint, watchpoint or HALT
+  b, break <label|n>   break at a label or source line n (file:n for included files)
+  w, watch <what>      stop when R0-R7, a flag (eg: EQ) or @addr changes
+  d, delete <n>        delete breakpoint or watchpoint n
+  i, info              list breakpoints and watchpoints
+  r, regs              print registers, flags and ports
+  m, mem <addr> [n]    print n words of data memory
+  in <value>           set the IN port
+  l, list              show the current source line
+  reset                restart the program
+  q, quit              exit the debugger";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Watch {
+    Reg(Reg),
+    Flag(BitPos),
+    Mem(u16),
+}
+
+enum Point {
+    Break(u16),
+    Watch(Watch),
+}
+
+impl Watch {
+    fn parse(arg: &str) -> Option<Self> {
+        if let Some(addr) = arg.strip_prefix('@') {
+            return parse_number(addr).map(Watch::Mem);
+        }
+        let arg = arg.to_lowercase();
+        if let Some(num) = arg.strip_prefix('r') {
+            if let Ok(num @ 0..=7) = num.parse() {
+                return Some(Watch::Reg(Reg(num)));
+            }
+        }
+        FLAG_NAMES
+            .iter()
+            .position(|name| name.to_lowercase() == arg)
+            .map(|pos| Watch::Flag(BitPos(pos as u8)))
+    }
+
+    fn value(&self, machine: &Machine) -> u16 {
+        match *self {
+            Watch::Reg(reg) => machine.reg(reg),
+            Watch::Flag(pos) => machine.flag(pos) as u16,
+            Watch::Mem(addr) => machine.mem[addr as usize],
+        }
+    }
+
+    fn name(&self) -> String {
+        match *self {
+            Watch::Reg(reg) => format!("R{}", reg.0),
+            Watch::Flag(pos) => FLAG_NAMES[pos.0 as usize].to_string(),
+            Watch::Mem(addr) => format!("@{addr}"),
+        }
+    }
+}
+
+struct Debugger<'a> {
+    sources: &'a Sources,
+    program: &'a Program,
+    machine: Machine,
+    points: Vec<Option<Point>>,
+}
+
+impl Debugger<'_> {
+    fn location(&self, pc: u16) -> String {
+        match self.program.lines.get(pc as usize) {
+            Some(&line) => format!(
+                "{:>4} | {}",
+                self.sources.short(line),
+                self.sources.text(line).trim()
+            ),
+            None => "     | <end of program>".to_string(),
+        }
+    }
+
+    fn show_current(&self) {
+        println!(
+            "pc={:<4} {}",
+            self.machine.pc,
+            self.location(self.machine.pc)
+        );
+    }
+
+    fn print_regs(&self) {
+        let m = &self.machine;
+        for (i, value) in m.regs.iter().enumerate() {
+            print!("R{i}={value:#06x} ");
+        }
+        println!();
+        let flags: Vec<&str> = FLAG_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| m.flag(BitPos(*i as u8)))
+            .map(|(_, name)| *name)
+            .collect();
+        println!(
+            "pc={} br={} flags=[{}] in={:#06x} out={:#06x}",
+            m.pc,
+            m.branch,
+            flags.join(" "),
+            m.in_port,
+            m.out_port
+        );
+    }
+
+    // execute one instruction, returning why execution should stop, if it should
+    fn step(&mut self) -> Option<String> {
+        let watches: Vec<(usize, Watch, u16)> = self
+            .points
+            .iter()
+            .enumerate()
+            .filter_map(|(i, point)| match point {
+                Some(Point::Watch(watch)) => Some((i, *watch, watch.value(&self.machine))),
+                _ => None,
+            })
+            .collect();
+        match self.machine.step(self.program) {
+            Status::Halted => return Some("program halted".into()),
+            Status::OutOfBounds => return Some("program ran past the last instruction".into()),
+            Status::Running => {}
+        }
+        for (i, watch, old) in watches {
+            let new = watch.value(&self.machine);
+            if new != old {
+                return Some(format!(
+                    "watchpoint {i}: {} changed {old:#x} -> {new:#x}",
+                    watch.name()
+                ));
+            }
+        }
+        self.points
+            .iter()
+            .enumerate()
+            .find_map(|(i, point)| match point {
+                Some(Point::Break(addr)) if *addr == self.machine.pc => {
+                    Some(format!("breakpoint {i}"))
+                }
+                _ => None,
+            })
+    }
+
+    fn run(&mut self, count: Option<u64>) {
+        if self.machine.status != Status::Running {
+            println!("program is not running, use reset to restart it");
+            return;
+        }
+        let mut executed = 0;
+        let reason = loop {
+            if count.is_some_and(|count| executed >= count) {
+                break None;
+            }
+            executed += 1;
+            if let Some(reason) = self.step() {
+                break Some(reason);
+            }
+        };
+        if let Some(reason) = reason {
+            println!("{reason}");
+        }
+        self.show_current();
+    }
+
+    // n for a line of the main file, file:n for an included file
+    fn parse_line(&self, arg: &str) -> Option<Location> {
+        let (file, line) = match arg.rsplit_once(':') {
+            Some((name, line)) => {
+                let file = self
+                    .sources
+                    .files
+                    .iter()
+                    .position(|file| file.name == name)?;
+                (file, line)
+            }
+            None => (0, arg),
+        };
+        let line = line.parse().ok()?;
+        Some(Location { file, line })
+    }
+
+    fn add_break(&mut self, arg: &str) {
+        let address = match self.parse_line(arg) {
+            Some(line) => self.program.address_of_line(line),
+            None => self.program.labels.get(arg).copied().or_else(|| {
+                self.program
+                    .labels
+                    .iter()
+                    .find(|(label, _)| label.eq_ignore_ascii_case(arg))
+                    .map(|(_, address)| *address)
+            }),
+        };
+        match address {
+            Some(address) => {
+                println!(
+                    "breakpoint {} at pc={} {}",
+                    self.points.len(),
+                    address.0,
+                    self.location(address.0)
+                );
+                self.points.push(Some(Point::Break(address.0)));
+            }
+            None => println!("no label or instruction found for {arg:?}"),
+        }
+    }
+
+    fn info(&self) {
+        for (i, point) in self.points.iter().enumerate() {
+            match point {
+                Some(Point::Break(addr)) => println!("{i}: break {}", self.location(*addr)),
+                Some(Point::Watch(watch)) => println!("{i}: watch {}", watch.name()),
+                None => {}
+            }
+        }
+    }
+
+    // handles one command, returning false when the debugger should exit
+    fn command(&mut self, input: &str) -> bool {
+        let mut words = input.split_whitespace();
+        let Some(command) = words.next() else {
+            return true;
+        };
+        let arg = words.next();
+        match (command, arg) {
+            ("s" | "step", None) => self.run(Some(1)),
+            ("s" | "step", Some(n)) => match n.parse() {
+                Ok(n) => self.run(Some(n)),
+                Err(_) => println!("invalid step count {n:?}"),
+            },
+            ("c" | "continue", _) => self.run(None),
+            ("b" | "break", Some(arg)) => self.add_break(arg),
+            ("w" | "watch", Some(arg)) => match Watch::parse(arg) {
+                Some(watch) => {
+                    println!("watchpoint {}: {}", self.points.len(), watch.name());
+                    self.points.push(Some(Point::Watch(watch)));
+                }
+                None => println!("cannot watch {arg:?}, expected R0-R7, a flag or @addr"),
+            },
+            ("d" | "delete", Some(n)) => match n.parse::<usize>() {
+                Ok(n) if self.points.get(n).is_some_and(Option::is_some) => self.points[n] = None,
+                _ => println!("no breakpoint or watchpoint {n:?}"),
+            },
+            ("i" | "info", _) => self.info(),
+            ("r" | "regs", _) => self.print_regs(),
+            ("m" | "mem", Some(addr)) => {
+                let count = words.next().and_then(|n| n.parse().ok()).unwrap_or(1);
+                match parse_number(addr) {
+                    Some(addr) => {
+                        for addr in (addr as usize..)
+                            .take(count)
+                            .filter(|&a| a < self.machine.mem.len())
+                        {
+                            println!("@{addr:#06x} = {:#06x}", self.machine.mem[addr]);
+                        }
+                    }
+                    None => println!("invalid address {addr:?}"),
+                }
+            }
+            ("in", Some(value)) => match parse_number(value) {
+                Some(value) => self.machine.in_port = value,
+                None => println!("invalid value {value:?}"),
+            },
+            ("l" | "list", _) => self.show_current(),
+            ("reset", _) => {
+                self.machine = Machine::load(self.program);
+                self.show_current();
+            }
+            ("q" | "quit", _) => return false,
+            ("h" | "help", _) => println!("{HELP}"),
+            _ => println!("unknown command {input:?}, type help for a list of commands"),
+        }
+        true
+    }
+}
+
+pub fn run(sources: &Sources, program: &Program) {
+    let mut debugger = Debugger {
+        sources,
+        program,
+        machine: Machine::load(program),
+        points: Vec::new(),
+    };
+    debugger.show_current();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(dbg) ");
+        io::stdout().flush().expect("could not flush stdout");
+        match lines.next() {
+            Some(Ok(line)) => {
+                if !debugger.command(line.trim()) {
+                    break;
+                }
+            }
+            _ => break,
+        }
+    }
+}
@@ -0,0 +1,239 @@
+// Constant expressions for .equ, .if and the like, with C precedence:
+//
+//   ( )   - ~ !   * / %   + -   << >>   < <= > >=   == !=   &   ^   |   &&   ||
+//
+// Numbers are decimal, 0x hexadecimal or 0b binary, names are looked up with the
+// function given, which decides whether case matters. Comparisons and logical
+// operators give 1 or 0.
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+// longest first, so `<<` is not read as two `<`
+const OPS: [&str; 22] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "(", ")", "-", "~", "!", "*", "/", "%", "+",
+    "<", ">", "&", "^", "|",
+];
+
+// binary operators from loosest to tightest binding
+const LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+// how deep parentheses and unary operators may nest, each level is a few calls deep
+// on the stack
+const MAX_DEPTH: usize = 100;
+
+// decimal, 0x hexadecimal or 0b binary, the number formats of the whole assembler
+pub fn parse_number(text: &str) -> Option<i64> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        if c.is_alphanumeric() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            tokens.push(match c.is_ascii_digit() {
+                true => Token::Number(
+                    parse_number(&word.to_lowercase())
+                        .ok_or_else(|| format!("invalid number {word}"))?,
+                ),
+                false => Token::Name(word.to_string()),
+            });
+            rest = &rest[end..];
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(format!("unexpected {c:?} in expression"));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+    constant: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| LEVELS[level].contains(op)) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            value = match op {
+                "||" => (value != 0 || rhs != 0) as i64,
+                "&&" => (value != 0 && rhs != 0) as i64,
+                "|" => value | rhs,
+                "^" => value ^ rhs,
+                "&" => value & rhs,
+                "==" => (value == rhs) as i64,
+                "!=" => (value != rhs) as i64,
+                "<" => (value < rhs) as i64,
+                "<=" => (value <= rhs) as i64,
+                ">" => (value > rhs) as i64,
+                ">=" => (value >= rhs) as i64,
+                "<<" => value.wrapping_shl(rhs as u32),
+                ">>" => value.wrapping_shr(rhs as u32),
+                "+" => value.wrapping_add(rhs),
+                "-" => value.wrapping_sub(rhs),
+                "*" => value.wrapping_mul(rhs),
+                "/" | "%" if rhs == 0 => return Err("division by zero".to_string()),
+                // only i64::MIN / -1 is left to overflow
+                "/" => value.checked_div(rhs).ok_or("division overflows")?,
+                _ => value.checked_rem(rhs).ok_or("division overflows")?,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("expression nested more than {MAX_DEPTH} deep"));
+        }
+        self.depth += 1;
+        let value = self.operand();
+        self.depth -= 1;
+        value
+    }
+
+    fn operand(&mut self) -> Result<i64, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Name(name)) => {
+                (self.constant)(&name).ok_or_else(|| format!("undefined constant {name}"))
+            }
+            Some(Token::Op("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(Token::Op("~")) => Ok(!self.unary()?),
+            Some(Token::Op("!")) => Ok((self.unary()? == 0) as i64),
+            Some(Token::Op("(")) => {
+                let value = self.binary(0)?;
+                match self.peek_op() {
+                    Some(")") => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            Some(Token::Op(op)) => Err(format!("unexpected {op} in expression")),
+            None => Err("expression ended early".to_string()),
+        }
+    }
+}
+
+pub fn eval(text: &str, constant: impl Fn(&str) -> Option<i64>) -> Result<i64, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+        depth: 0,
+        constant: &constant,
+    };
+    let value = parser.binary(0)?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(value),
+        Some(_) => Err(format!("unexpected text after expression: {text}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str) -> Result<i64, String> {
+        eval(text, |name| (name == "SIZE").then_some(8))
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(value("1 + 2 * 3"), Ok(7));
+        assert_eq!(value("(1 + 2) * 3"), Ok(9));
+        assert_eq!(value("10 - 4 - 3"), Ok(3));
+        assert_eq!(value("1 << 2 + 1"), Ok(8));
+        assert_eq!(value("1 | 6 & 3"), Ok(3));
+        assert_eq!(value("1 ^ 3 | 4"), Ok(6));
+        assert_eq!(value("2 + 3 == 5"), Ok(1));
+        assert_eq!(value("1 < 2 == 1"), Ok(1));
+        assert_eq!(value("0 || 1 && 0"), Ok(0));
+        assert_eq!(value("-2 * -3"), Ok(6));
+        assert_eq!(value("~0 & 0xff"), Ok(255));
+        assert_eq!(value("!SIZE + 1"), Ok(1));
+        assert_eq!(value("SIZE * 0b10 % 5"), Ok(1));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(value("1 / 0"), Err("division by zero".to_string()));
+        assert_eq!(value("1 % (SIZE - 8)"), Err("division by zero".to_string()));
+        let min = "(-0x7fffffffffffffff - 1)";
+        assert_eq!(
+            value(&format!("{min} / -1")),
+            Err("division overflows".to_string())
+        );
+        assert_eq!(
+            value(&format!("{min} % -1")),
+            Err("division overflows".to_string())
+        );
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(value(&nested(MAX_DEPTH - 1)), Ok(1));
+        assert_eq!(
+            value(&nested(100_000)),
+            Err(format!("expression nested more than {MAX_DEPTH} deep"))
+        );
+        assert!(value(&"-".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(value("OTHER"), Err("undefined constant OTHER".to_string()));
+        assert_eq!(value("(1 + 2"), Err("missing )".to_string()));
+        assert_eq!(value("1 +"), Err("expression ended early".to_string()));
+        assert_eq!(value("0x"), Err("invalid number 0x".to_string()));
+        assert!(value("1 2").is_err());
+    }
+}
@@ -0,0 +1,347 @@
+// GDB remote serial protocol stub, serving the execution model to a debugger frontend.
+// Instruction addresses (pc, br and breakpoints) are instruction indices, while
+// memory reads and writes go to data memory with each 16 bit word taking two bytes
+// (little endian), so word n lives at byte address 2n.
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    program::Program,
+    sim::{Machine, Status},
+};
+
+const REG_NAMES: [&str; 13] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "pc", "br", "flags", "in", "out",
+];
+
+// how many instructions run between checks for a ctrl-c from the frontend
+const INTERRUPT_POLL: u64 = 4096;
+
+pub trait Transport: Read + Write {
+    // true if the frontend sent an interrupt (0x03) while the target was running
+    fn interrupted(&mut self) -> bool {
+        false
+    }
+}
+
+impl Transport for TcpStream {
+    fn interrupted(&mut self) -> bool {
+        let mut byte = [0];
+        self.set_nonblocking(true)
+            .expect("could not poll gdb connection");
+        let interrupted = matches!(self.read(&mut byte), Ok(1) if byte[0] == 0x03);
+        self.set_nonblocking(false)
+            .expect("could not poll gdb connection");
+        interrupted
+    }
+}
+
+pub struct Stdio;
+
+impl Read for Stdio {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::stdin().read(buf)
+    }
+}
+
+impl Write for Stdio {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stdout().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+impl Transport for Stdio {}
+
+pub fn target_xml() -> String {
+    let mut xml = vec![
+        r#"<?xml version="1.0"?>"#.to_string(),
+        r#"<!DOCTYPE target SYSTEM "gdb-target.dtd">"#.to_string(),
+        r#"<target version="1.0">"#.to_string(),
+        r#"  <feature name="org.mini-risc.core">"#.to_string(),
+    ];
+    for (i, name) in REG_NAMES.iter().enumerate() {
+        let kind = match *name {
+            "pc" | "br" => "code_ptr",
+            _ => "uint16",
+        };
+        xml.push(format!(
+            r#"    <reg name="{name}" bitsize="16" type="{kind}" regnum="{i}"/>"#
+        ));
+    }
+    xml.extend(["  </feature>".to_string(), "</target>".to_string()]);
+    xml.join("\n")
+}
+
+fn hex_u16(value: u16) -> String {
+    let [low, high] = value.to_le_bytes();
+    format!("{low:02x}{high:02x}")
+}
+
+fn parse_hex(hex: &str) -> Option<u32> {
+    u32::from_str_radix(hex, 16).ok()
+}
+
+fn decode_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+struct Stub<'a, T: Transport> {
+    conn: T,
+    program: &'a Program,
+    machine: Machine,
+    breakpoints: Vec<u16>,
+}
+
+impl<T: Transport> Stub<'_, T> {
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        match self.conn.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    // reads the next packet, returning None when the connection closes
+    fn read_packet(&mut self) -> Option<String> {
+        loop {
+            match self.read_byte()? {
+                b'$' => break,
+                // interrupt while stopped, answered like any other stop
+                0x03 => return Some("?".into()),
+                _ => continue,
+            }
+        }
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte()? {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.read_byte()?, self.read_byte()?];
+        let expected = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        let valid = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(parse_hex)
+            .is_some_and(|sum| sum == expected as u32);
+        self.conn.write_all(if valid { b"+" } else { b"-" }).ok()?;
+        if !valid {
+            return self.read_packet();
+        }
+        Some(String::from_utf8_lossy(&data).into_owned())
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.conn, "${data}#{checksum:02x}")?;
+        self.conn.flush()
+    }
+
+    fn reg(&self, n: usize) -> Option<u16> {
+        let m = &self.machine;
+        Some(match n {
+            0..=7 => m.regs[n],
+            8 => m.pc,
+            9 => m.branch,
+            10 => m.flags as u16,
+            11 => m.in_port,
+            12 => m.out_port,
+            _ => return None,
+        })
+    }
+
+    fn set_reg(&mut self, n: usize, value: u16) -> bool {
+        let m = &mut self.machine;
+        match n {
+            0..=7 => m.regs[n] = value,
+            8 => m.pc = value,
+            9 => m.branch = value,
+            10 => m.flags = value as u8,
+            11 => m.in_port = value,
+            12 => m.out_port = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn stop_reply(&self, signal: u8) -> String {
+        match self.machine.status {
+            Status::Halted => "W00".into(),
+            // SIGSEGV
+            Status::OutOfBounds => "S0b".into(),
+            Status::Running => format!("S{signal:02x}"),
+        }
+    }
+
+    fn resume(&mut self, single_step: bool) -> String {
+        let mut steps: u64 = 0;
+        loop {
+            self.machine.step(self.program);
+            steps += 1;
+            if single_step || self.machine.status != Status::Running {
+                break;
+            }
+            if self.breakpoints.contains(&self.machine.pc) {
+                break;
+            }
+            if steps.is_multiple_of(INTERRUPT_POLL) && self.conn.interrupted() {
+                // SIGINT
+                return self.stop_reply(2);
+            }
+        }
+        // SIGTRAP
+        self.stop_reply(5)
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, len) = args.split_once(',')?;
+        let (addr, len) = (parse_hex(addr)? as usize, parse_hex(len)? as usize);
+        let mut reply = String::new();
+        for byte_addr in addr..addr + len {
+            let word = *self.machine.mem.get(byte_addr / 2)?;
+            reply += &format!("{:02x}", word.to_le_bytes()[byte_addr % 2]);
+        }
+        Some(reply)
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<()> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, _) = range.split_once(',')?;
+        let addr = parse_hex(addr)? as usize;
+        for (i, byte) in decode_hex_bytes(data)?.into_iter().enumerate() {
+            let word = self.machine.mem.get_mut((addr + i) / 2)?;
+            let mut bytes = word.to_le_bytes();
+            bytes[(addr + i) % 2] = byte;
+            *word = u16::from_le_bytes(bytes);
+        }
+        Some(())
+    }
+
+    fn features(&self, args: &str) -> Option<String> {
+        let (annex, range) = args.split_once(':')?;
+        if annex != "target.xml" {
+            return Some("E00".into());
+        }
+        let (offset, len) = range.split_once(',')?;
+        let (offset, len) = (parse_hex(offset)? as usize, parse_hex(len)? as usize);
+        let xml = target_xml();
+        let chunk = xml.get(offset.min(xml.len())..(offset + len).min(xml.len()))?;
+        let more = if offset + len < xml.len() { 'm' } else { 'l' };
+        Some(format!("{more}{chunk}"))
+    }
+
+    // returns the reply for a packet, or None if the session should end
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        let error = || "E01".to_string();
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        Some(match command {
+            "?" => self.stop_reply(5),
+            "g" => (0..REG_NAMES.len())
+                .filter_map(|n| self.reg(n))
+                .map(hex_u16)
+                .collect(),
+            "G" => match decode_hex_bytes(args) {
+                Some(bytes) if bytes.len() >= REG_NAMES.len() * 2 => {
+                    for (n, pair) in bytes.chunks(2).take(REG_NAMES.len()).enumerate() {
+                        self.set_reg(n, u16::from_le_bytes([pair[0], pair[1]]));
+                    }
+                    "OK".into()
+                }
+                _ => error(),
+            },
+            "p" => parse_hex(args)
+                .and_then(|n| self.reg(n as usize))
+                .map_or_else(error, hex_u16),
+            "P" => {
+                let value = args.split_once('=').and_then(|(n, value)| {
+                    let bytes = decode_hex_bytes(value)?;
+                    let value = u16::from_le_bytes([*bytes.first()?, *bytes.get(1).unwrap_or(&0)]);
+                    Some((parse_hex(n)? as usize, value))
+                });
+                match value {
+                    Some((n, value)) if self.set_reg(n, value) => "OK".into(),
+                    _ => error(),
+                }
+            }
+            "m" => self.read_memory(args).unwrap_or_else(error),
+            "M" => self.write_memory(args).map_or_else(error, |_| "OK".into()),
+            "s" | "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.machine.pc = addr as u16;
+                }
+                self.resume(command == "s")
+            }
+            "Z" | "z" => {
+                let mut fields = args.split(',');
+                let (kind, addr) = (fields.next(), fields.next().and_then(parse_hex));
+                match (kind, addr) {
+                    // software and hardware breakpoints are treated the same
+                    (Some("0" | "1"), Some(addr)) => {
+                        let addr = addr as u16;
+                        self.breakpoints.retain(|&bp| bp != addr);
+                        if command == "Z" {
+                            self.breakpoints.push(addr);
+                        }
+                        "OK".into()
+                    }
+                    _ => String::new(),
+                }
+            }
+            "H" => "OK".into(),
+            "k" => return None,
+            "D" => {
+                self.send("OK").ok()?;
+                return None;
+            }
+            "q" if args.starts_with("Supported") => "PacketSize=4000;qXfer:features:read+".into(),
+            "q" if args == "Attached" => "1".into(),
+            "q" if args == "C" => "QC1".into(),
+            "q" => match args.strip_prefix("Xfer:features:read:") {
+                Some(args) => self.features(args).unwrap_or_else(error),
+                None => String::new(),
+            },
+            _ => String::new(),
+        })
+    }
+}
+
+pub fn serve<T: Transport>(conn: T, program: &Program) {
+    let mut stub = Stub {
+        conn,
+        program,
+        machine: Machine::load(program),
+        breakpoints: Vec::new(),
+    };
+    while let Some(packet) = stub.read_packet() {
+        match stub.handle(&packet) {
+            Some(reply) => {
+                if stub.send(&reply).is_err() {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+}
+
+pub fn listen(port: u16, program: &Program) {
+    let listener = TcpListener::bind(("127.0.0.1", port)).expect("GDB ERR: could not bind port");
+    eprintln!("waiting for gdb on 127.0.0.1:{port}");
+    let (conn, addr) = listener
+        .accept()
+        .expect("GDB ERR: could not accept connection");
+    eprintln!("gdb connected from {addr}");
+    serve(conn, program);
+}
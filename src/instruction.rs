@@ -1,8 +1,16 @@
-use std::collections::HashMap;
+use crate::{
+    opcodes::{Opcodes, OPCODE_BITS},
+    parse_file::branch_mnemonic,
+    program::{self, Program, Section},
+    stack::{Direction, Stack},
+    types::{BitPos, Reg, FLAG_NAMES, IO},
+};
 
-use crate::types::{Address, BitPos, Reg};
+// flag forced by JMP to make its JF unconditional
+pub const JMP_FLAG: BitPos = IO;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Op {
     ADD(Reg, Reg, Reg),
     MUL(Reg, Reg),
@@ -36,10 +44,36 @@ pub enum Op {
     // Psuedo Instructions
     Label(String),
     LoadByte(Reg, u8, u8),
+    Jmp(String),
+    // jumps to the label if the flag is set, or clear when negated
+    Branch(BitPos, bool, String),
+    Call(String),
+    Ret,
+    Push(Reg),
+    Pop(Reg),
+    Stack(Stack, u16), // stack base address
+    Nop,
+    Clr(Reg),
+    Neg(Reg),
+    Dec(Reg),
+    Shl(Reg),
+    Shr(Reg),
+    Tst(Reg),
+    // MOV Rn, #label for a data label, replaced by LoadByte once data is laid out
+    LoadLabel(Reg, String),
+    // directives for data memory, see Program::new
+    Section(Section),
+    Words(Vec<u16>),
+    // location control, see Program::new
+    Org(u16),
+    Align(u16),
+    // symbol visibility, see object
+    Global(Vec<String>),
+    Extern(Vec<String>),
 }
 
 impl Op {
-    pub fn to_verilog(&self, labels: &HashMap<String, Address>) -> String {
+    pub fn to_verilog(&self, program: &Program) -> String {
         let opcode = self.opcode();
         let mut result = format!("{{{opcode}, ");
         result += &match self {
@@ -61,7 +95,7 @@ impl Op {
             Op::SETF(flag_bit_pos) => format!("6'bx, {flag_bit_pos}, 1'bx"),
             Op::CLRF(flag_bit_pos) => format!("6'bx, {flag_bit_pos}, 1'bx"),
             Op::CPLF(flag_bit_pos) => format!("6'bx, {flag_bit_pos}, 1'bx"),
-            Op::LOADBR(label) => format!("{}", labels[label]),
+            Op::LOADBR(label) => format!("{}", program.label_address(label)),
             Op::JF(flag_bit_pos) => format!("6'bx, {flag_bit_pos}, 1'bx"),
             Op::LOAD(dest_reg, source_addr_reg) => format!("{dest_reg}, {source_addr_reg}, 5'bx"),
             Op::STORE(dest_addr_reg, source_reg) => {
@@ -73,13 +107,59 @@ impl Op {
             Op::MOVOUT(reg) => format!("3'bx, {reg}, 5'bx"),
             Op::MOVIN(reg) => format!("{reg}, 8'bx"),
             Op::MOVB(port_bit_pos) => format!("3'bx, {port_bit_pos}, 4'bx"),
-            Op::HALT => format!("11'bx"),
+            Op::HALT => "11'bx".to_string(),
             _ => panic!("have not implemented to verilog for {self:?}"),
         };
         result + "};"
     }
 
-    fn opcode(&self) -> String {
+    // the instruction as a 16 bit word: the processor's value for the opcode in the top
+    // bits, then the fields in the order to_verilog gives them, with don't care bits as 0
+    pub fn encode(&self, program: &Program, opcodes: &Opcodes) -> u16 {
+        let opcode = opcodes.get(&self.opcode());
+        // (value, width) from the most significant field down
+        let fields: Vec<(u16, u32)> = match self {
+            Op::ADD(out, in1, in2)
+            | Op::SUB(out, in1, in2)
+            | Op::AND(out, in1, in2)
+            | Op::OR(out, in1, in2)
+            | Op::XOR(out, in1, in2) => {
+                vec![(out.0 as u16, 3), (in1.0 as u16, 3), (in2.0 as u16, 3)]
+            }
+            Op::MUL(in1, in2) | Op::DIV(in1, in2) | Op::CMP(in1, in2) | Op::STORE(in1, in2) => {
+                vec![(0, 3), (in1.0 as u16, 3), (in2.0 as u16, 3)]
+            }
+            Op::NOT(out, in1)
+            | Op::INC(out, in1)
+            | Op::RR(out, in1)
+            | Op::RL(out, in1)
+            | Op::LOAD(out, in1)
+            | Op::MOV(out, in1) => vec![(out.0 as u16, 3), (in1.0 as u16, 3)],
+            Op::SETB(reg, pos) | Op::CLRB(reg, pos) | Op::CPLB(reg, pos) => {
+                vec![(reg.0 as u16, 3), (reg.0 as u16, 3), (pos.0 as u16, 4)]
+            }
+            Op::SETF(pos) | Op::CLRF(pos) | Op::CPLF(pos) | Op::JF(pos) => {
+                vec![(0, 6), (pos.0 as u16, 4)]
+            }
+            Op::LOADBR(label) => vec![(program.label_address(label).0, 11)],
+            Op::LBL(reg, byte) | Op::LBH(reg, byte) => vec![(reg.0 as u16, 3), (*byte as u16, 8)],
+            Op::MOVOUT(reg) => vec![(0, 3), (reg.0 as u16, 3)],
+            Op::MOVIN(reg) => vec![(reg.0 as u16, 3)],
+            Op::MOVB(pos) => vec![(0, 3), (pos.0 as u16, 4)],
+            _ => vec![],
+        };
+        let mut word = opcode;
+        let mut width = 0;
+        for (value, bits) in fields {
+            // a value too wide would spill into the fields before it
+            debug_assert!(value >> bits == 0, "{value} does not fit in {bits} bits");
+            word = (word << bits) | (value & ((1 << bits) - 1));
+            width += bits;
+        }
+        word << (16 - OPCODE_BITS - width)
+    }
+
+    pub fn opcode(&self) -> String {
         match self {
             Op::ADD(_, _, _) => "ADD",
             Op::MUL(_, _) => "MUL",
@@ -119,8 +199,203 @@ impl Op {
         use Op::*;
         match self {
             LoadByte(reg, upper, lower) => vec![LBH(reg, upper), LBL(reg, lower)],
+            Jmp(label) => vec![LOADBR(label), SETF(JMP_FLAG), JF(JMP_FLAG)],
+            Branch(flag, false, label) => vec![LOADBR(label), JF(flag)],
+            // the flag is left complemented whether or not the jump is taken
+            Branch(flag, true, label) => vec![LOADBR(label), CPLF(flag), JF(flag)],
+            Nop => vec![MOV(Reg(0), Reg(0))],
+            Clr(reg) => vec![XOR(reg, reg, reg)],
+            Neg(reg) => vec![NOT(reg, reg), INC(reg, reg)],
+            // ~(~x + 1) = x - 1
+            Dec(reg) => vec![NOT(reg, reg), INC(reg, reg), NOT(reg, reg)],
+            // rotate, then clear the bit that wrapped around
+            Shl(reg) => vec![RL(reg, reg), CLRB(reg, BitPos(0))],
+            Shr(reg) => vec![RR(reg, reg), CLRB(reg, BitPos(15))],
+            Tst(reg) => vec![OR(reg, reg, reg)],
             // if its already a single op
             op => vec![op],
         }
     }
+
+    // the instruction in assembly syntax, as shown in listings
+    pub fn to_asm(&self) -> String {
+        let flag = |pos: &BitPos| FLAG_NAMES[pos.0 as usize];
+        let opcode = self.opcode();
+        match self {
+            Op::ADD(out, in1, in2)
+            | Op::SUB(out, in1, in2)
+            | Op::AND(out, in1, in2)
+            | Op::OR(out, in1, in2)
+            | Op::XOR(out, in1, in2) => format!("{opcode} R{}, R{}, R{}", out.0, in1.0, in2.0),
+            Op::MUL(in1, in2)
+            | Op::DIV(in1, in2)
+            | Op::CMP(in1, in2)
+            | Op::NOT(in1, in2)
+            | Op::INC(in1, in2)
+            | Op::RR(in1, in2)
+            | Op::RL(in1, in2)
+            | Op::MOV(in1, in2) => format!("{opcode} R{}, R{}", in1.0, in2.0),
+            Op::SETB(reg, pos) | Op::CLRB(reg, pos) | Op::CPLB(reg, pos) => {
+                format!("{opcode} R{}.{}", reg.0, pos.0)
+            }
+            Op::SETF(pos) | Op::CLRF(pos) | Op::CPLF(pos) | Op::JF(pos) => {
+                format!("{opcode} {}", flag(pos))
+            }
+            Op::LOADBR(label) => format!("{opcode} {label}"),
+            Op::LOAD(dest, addr) => format!("{opcode} R{}, @R{}", dest.0, addr.0),
+            Op::STORE(addr, source) => format!("{opcode} @R{}, R{}", addr.0, source.0),
+            Op::LBL(reg, byte) | Op::LBH(reg, byte) => format!("{opcode} R{}, #{byte}", reg.0),
+            Op::MOVOUT(reg) | Op::MOVIN(reg) => format!("{opcode} R{}", reg.0),
+            Op::MOVB(pos) => format!("{opcode} IN.{}", pos.0),
+            Op::HALT => opcode,
+            _ => panic!("have not implemented to asm for {self:?}"),
+        }
+    }
+
+    // the op in the syntax the parser reads, which objects store instructions in
+    pub fn to_source(&self) -> String {
+        use Op::*;
+        let flag = |pos: &BitPos| FLAG_NAMES[pos.0 as usize];
+        match self {
+            SETB(reg, pos) => format!("SET R{}.{}", reg.0, pos.0),
+            CLRB(reg, pos) => format!("CLR R{}.{}", reg.0, pos.0),
+            CPLB(reg, pos) => format!("CPL R{}.{}", reg.0, pos.0),
+            SETF(pos) => format!("SET {}", flag(pos)),
+            CLRF(pos) => format!("CLR {}", flag(pos)),
+            CPLF(pos) => format!("CPL {}", flag(pos)),
+            LOAD(dest, addr) => format!("MOV R{}, @R{}", dest.0, addr.0),
+            STORE(addr, source) => format!("MOV @R{}, R{}", addr.0, source.0),
+            MOVOUT(reg) => format!("MOV OUT, R{}", reg.0),
+            MOVIN(reg) => format!("MOV R{}, IN", reg.0),
+            Label(label) => format!("{label}:"),
+            LoadByte(reg, upper, lower) => {
+                format!("MOV R{}, #{}", reg.0, u16::from_be_bytes([*upper, *lower]))
+            }
+            LoadLabel(reg, label) => format!("MOV R{}, #{label}", reg.0),
+            Jmp(label) => format!("JMP {label}"),
+            Branch(pos, negate, label) => {
+                format!("{} {label}", branch_mnemonic(*pos, *negate).to_uppercase())
+            }
+            Call(label) => format!("CALL {label}"),
+            Ret => "RET".to_string(),
+            Push(reg) => format!("PUSH R{}", reg.0),
+            Pop(reg) => format!("POP R{}", reg.0),
+            Stack(stack, base) => {
+                let direction = match stack.direction {
+                    Direction::Up => "up",
+                    Direction::Down => "down",
+                };
+                format!(
+                    ".stack R{}, {base}, R{}, {direction}",
+                    stack.sp.0, stack.scratch.0
+                )
+            }
+            Nop => "NOP".to_string(),
+            Clr(reg) => format!("CLR R{}", reg.0),
+            Neg(reg) => format!("NEG R{}", reg.0),
+            Dec(reg) => format!("DEC R{}", reg.0),
+            Shl(reg) => format!("SHL R{}", reg.0),
+            Shr(reg) => format!("SHR R{}", reg.0),
+            Tst(reg) => format!("TST R{}", reg.0),
+            Section(program::Section::Text) => ".text".to_string(),
+            Section(program::Section::Data) => ".data".to_string(),
+            Words(words) if words.is_empty() => ".space 0".to_string(),
+            Words(words) => {
+                let words: Vec<String> = words.iter().map(u16::to_string).collect();
+                format!(".word {}", words.join(", "))
+            }
+            Org(address) => format!(".org {address}"),
+            Align(size) => format!(".align {size}"),
+            Global(names) => format!(".global {}", names.join(", ")),
+            Extern(names) => format!(".extern {}", names.join(", ")),
+            // the listing syntax of the rest is also source syntax
+            op => op.to_asm(),
+        }
+    }
+
+    // the symbol an op refers to, which objects keep relocations for
+    pub fn symbol(&mut self) -> Option<&mut String> {
+        match self {
+            Op::LOADBR(label)
+            | Op::Jmp(label)
+            | Op::Branch(_, _, label)
+            | Op::Call(label)
+            | Op::LoadLabel(_, label) => Some(label),
+            _ => None,
+        }
+    }
+
+    // pseudo instructions that are not part of the ISA, rejected by --no-pseudo
+    pub fn is_pseudo(&self) -> bool {
+        use Op::*;
+        matches!(
+            self,
+            Jmp(_)
+                | Branch(_, _, _)
+                | Call(_)
+                | Ret
+                | Push(_)
+                | Pop(_)
+                | Nop
+                | Clr(_)
+                | Neg(_)
+                | Dec(_)
+                | Shl(_)
+                | Shr(_)
+                | Tst(_)
+        )
+    }
+
+    // ops that do not assemble to instructions themselves, or only once expanded
+    pub fn is_directive(&self) -> bool {
+        use Op::*;
+        matches!(
+            self,
+            Label(_)
+                | LoadLabel(_, _)
+                | Call(_)
+                | Ret
+                | Push(_)
+                | Pop(_)
+                | Stack(_, _)
+                | Section(_)
+                | Words(_)
+                | Org(_)
+                | Align(_)
+                | Global(_)
+                | Extern(_)
+        )
+    }
+
+    // the register an instruction writes to, if any
+    pub fn writes(&self) -> Option<Reg> {
+        match *self {
+            Op::ADD(out, _, _)
+            | Op::SUB(out, _, _)
+            | Op::AND(out, _, _)
+            | Op::OR(out, _, _)
+            | Op::XOR(out, _, _)
+            | Op::NOT(out, _)
+            | Op::INC(out, _)
+            | Op::RR(out, _)
+            | Op::RL(out, _)
+            | Op::SETB(out, _)
+            | Op::CLRB(out, _)
+            | Op::CPLB(out, _)
+            | Op::LOAD(out, _)
+            | Op::LBL(out, _)
+            | Op::LBH(out, _)
+            | Op::MOV(out, _)
+            | Op::MOVIN(out)
+            | Op::LoadByte(out, _, _)
+            | Op::Pop(out)
+            | Op::Clr(out)
+            | Op::Neg(out)
+            | Op::Dec(out)
+            | Op::Shl(out)
+            | Op::Shr(out) => Some(out),
+            Op::MUL(_, _) | Op::DIV(_, _) => Some(Reg(0)),
+            _ => None,
+        }
+    }
 }
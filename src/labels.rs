@@ -0,0 +1,179 @@
+// Local labels, given names of their own before the symbols of a unit are collected:
+//
+// - `.loop` belongs to the global label before it, and becomes `name.loop`. Labels
+//   from macros (`%%label`) are not global labels, so they leave the scope as it is.
+// - numeric labels like `1:` can be defined any number of times. `1b` refers to the
+//   nearest `1:` before and `1f` to the nearest after, and the nth definition of
+//   `1` becomes `1$n`.
+//
+// Unless --case-sensitive is given, names that differ only in case are the same
+// label, and every use is spelled as where the label is defined.
+use std::collections::HashMap;
+
+use crate::{
+    instruction::Op,
+    preprocess::{Location, Sources},
+    Diagnostic,
+};
+
+fn is_numeric(label: &str) -> bool {
+    !label.is_empty() && label.bytes().all(|c| c.is_ascii_digit())
+}
+
+// every name spelled as where it is defined, or as first written if it is not. Runs
+// after scope, so the same local label in two routines is not defined twice.
+pub fn match_case(sources: &Sources, ops: &mut [(Location, Op)]) -> Result<(), Diagnostic> {
+    let mut spellings: HashMap<String, String> = HashMap::new();
+    let mut defined: HashMap<String, Location> = HashMap::new();
+    for (location, op) in ops.iter() {
+        if let Op::Label(label) = op {
+            // checked here, while the label is still spelled as written
+            if let Some(first) = defined.insert(label.to_lowercase(), *location) {
+                return Err(sources.error(
+                    *location,
+                    format!(
+                        "label {label:?} is already defined at {}",
+                        sources.describe(first)
+                    ),
+                ));
+            }
+            spellings.insert(label.to_lowercase(), label.clone());
+        }
+    }
+    let mut spell = |name: &mut String| {
+        let spelling = spellings
+            .entry(name.to_lowercase())
+            .or_insert_with(|| name.clone());
+        name.clone_from(spelling);
+    };
+    for (_, op) in ops.iter_mut() {
+        match op {
+            Op::Label(name) => spell(name),
+            Op::Global(names) | Op::Extern(names) => names.iter_mut().for_each(&mut spell),
+            op => {
+                if let Some(name) = op.symbol() {
+                    spell(name);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn scope(
+    sources: &Sources,
+    mut ops: Vec<(Location, Op)>,
+) -> Result<Vec<(Location, Op)>, Diagnostic> {
+    // positions of the definitions of each numeric label
+    let mut numeric: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, (_, op)) in ops.iter().enumerate() {
+        if let Op::Label(label) = op {
+            if is_numeric(label) {
+                numeric.entry(label.clone()).or_default().push(i);
+            }
+        }
+    }
+
+    let mut global: Option<String> = None;
+    for (i, (location, op)) in ops.iter_mut().enumerate() {
+        let location = *location;
+        let local = |global: &Option<String>, label: &str| match global {
+            Some(global) => Ok(format!("{global}{label}")),
+            None => Err(sources.error(
+                location,
+                format!("local label {label} has no global label before it"),
+            )),
+        };
+        match op {
+            Op::Label(label) if is_numeric(label) => {
+                let n = numeric[label.as_str()]
+                    .iter()
+                    .position(|&at| at == i)
+                    .unwrap();
+                *label = format!("{label}${n}");
+            }
+            Op::Label(label) if label.starts_with('.') => *label = local(&global, label)?,
+            // labels a macro expansion adds belong to the routine it is used in
+            Op::Label(label) if label.contains('@') => {}
+            Op::Label(label) => global = Some(label.clone()),
+            op => {
+                let Some(label) = op.symbol() else {
+                    continue;
+                };
+                if label.starts_with('.') {
+                    *label = local(&global, label)?;
+                    continue;
+                }
+                let (name, backward) = match (label.strip_suffix('b'), label.strip_suffix('f')) {
+                    (Some(name), _) => (name, true),
+                    (_, Some(name)) => (name, false),
+                    _ => continue,
+                };
+                if !is_numeric(name) {
+                    continue;
+                }
+                let definitions = numeric.get(name).map_or(&[][..], Vec::as_slice);
+                let n = match backward {
+                    true => definitions.iter().rposition(|&at| at < i),
+                    false => definitions.iter().position(|&at| at > i),
+                };
+                let Some(n) = n else {
+                    let side = match backward {
+                        true => "before",
+                        false => "after",
+                    };
+                    return Err(
+                        sources.error(location, format!("{label} refers to no {name}: {side} it"))
+                    );
+                };
+                *label = format!("{name}${n}");
+            }
+        }
+    }
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(ops: &[(Location, Op)]) -> Vec<String> {
+        ops.iter()
+            .map(|(_, op)| match op {
+                Op::Label(label) | Op::LOADBR(label) => label.clone(),
+                op => panic!("unexpected {op:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn macro_labels_keep_the_scope() {
+        let at = Location { file: 0, line: 1 };
+        let ops = ["main", ".loop", "over@1"]
+            .into_iter()
+            .map(|label| (at, Op::Label(label.to_string())))
+            .chain([(at, Op::LOADBR(".loop".to_string()))])
+            .collect();
+        let ops = scope(&Sources::from_names(&[]), ops).unwrap();
+        assert_eq!(names(&ops), ["main", "main.loop", "over@1", "main.loop"]);
+    }
+
+    #[test]
+    fn numeric_references() {
+        let at = Location { file: 0, line: 1 };
+        let ops = [
+            Op::Label("1".to_string()),
+            Op::LOADBR("1b".to_string()),
+            Op::LOADBR("1f".to_string()),
+            Op::Label("1".to_string()),
+            // not numeric references, and ending in a multibyte character
+            Op::LOADBR("é".to_string()),
+            Op::LOADBR("1é".to_string()),
+        ]
+        .into_iter()
+        .map(|op| (at, op))
+        .collect();
+        let ops = scope(&Sources::from_names(&[]), ops).unwrap();
+        assert_eq!(names(&ops), ["1$0", "1$0", "1$1", "1$1", "é", "1é"]);
+    }
+}
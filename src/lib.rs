@@ -0,0 +1,305 @@
+// The MINI-RISC assembler as a library, for test harnesses and build scripts:
+//
+//   let program = assembler::assemble("main:\n  HALT", &Options::default())?;
+//
+// The command line tool is built on the same modules. Errors come back as
+// diagnostics carrying the `ERR:` message the command line prints, along with the
+// file and line they were found at.
+
+pub mod archive;
+pub mod coverage;
+pub mod debugger;
+pub mod expr;
+pub mod gdb;
+pub mod instruction;
+mod labels;
+pub mod link;
+pub mod listing;
+pub mod object;
+pub mod opcodes;
+mod optimize;
+pub mod parse_file;
+pub mod preprocess;
+pub mod profile;
+pub mod program;
+pub mod sim;
+mod stack;
+pub mod testbench;
+pub mod timing;
+pub mod types;
+
+pub use instruction::Op;
+pub use preprocess::Location;
+pub use program::{Options, Program};
+pub use types::{Address, BitPos, Reg};
+
+use object::Object;
+use parse_file::Line;
+use preprocess::{SourceLine, Sources};
+use program::Section;
+use types::FLAG_NAMES;
+
+// the name the source given to assemble goes by, includes are looked up relative
+// to the current directory
+pub const SOURCE_NAME: &str = "<source>";
+
+// an error found while assembling
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    // the file and line when the error is tied to one, most messages also give it
+    pub file: Option<String>,
+    pub line: Option<usize>,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        Diagnostic {
+            message: message.into(),
+            file: None,
+            line: None,
+        }
+    }
+
+    // ties the error to a line, unless it already is
+    pub(crate) fn at(self, file: &str, line: usize) -> Self {
+        Diagnostic {
+            file: self.file.or_else(|| Some(file.to_string())),
+            line: self.line.or(Some(line)),
+            ..self
+        }
+    }
+}
+
+// assembles source as if it were a file on its own, with every line that does not
+// parse reported, or the first error found after that
+pub fn assemble(source: &str, options: &Options) -> Result<Program, Vec<Diagnostic>> {
+    let (sources, lines) =
+        preprocess::preprocess_str(SOURCE_NAME, source, options).map_err(|err| vec![err])?;
+
+    let mut ops = Vec::new();
+    let mut errors = Vec::new();
+    for line in lines {
+        match parse(&sources, &line) {
+            Ok(op) => ops.push((line, op)),
+            Err(err) => errors.push(err),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let program = || {
+        let (object, warnings) = unit(&sources, ops, options)?;
+        if let Some(name) = object.externs.first() {
+            return Err(Diagnostic::new(format!(
+                "ERR: {name} is declared .extern, which assemble cannot link"
+            )));
+        }
+        let mut program = Program::new(&sources, object.code, options)?;
+        program.warnings = warnings;
+        Ok(program)
+    };
+    program().map_err(|err| vec![err])
+}
+
+// a line parsed, with the error given at the line and the macros it is in
+fn parse(sources: &Sources, line: &SourceLine) -> Result<Op, Diagnostic> {
+    Line::from_str(&line.text)
+        .and_then(Line::to_instruction)
+        .map_err(|err| sources.line_error(line, format!("{}: {}", err.message, line.text.trim())))
+}
+
+// returns the source files read along with the unit assembled as an object, and the
+// warnings about it
+pub fn read_object(
+    file_name: &str,
+    options: &Options,
+) -> Result<(Sources, Object, Vec<Diagnostic>), Diagnostic> {
+    let (sources, lines) = preprocess::preprocess(file_name, options)?;
+    let mut ops = Vec::new();
+    for line in lines {
+        let op = parse(&sources, &line)?;
+        ops.push((line, op));
+    }
+    let (object, warnings) = unit(&sources, ops, options)?;
+    Ok((sources, object, warnings))
+}
+
+// checks the placement of the parsed lines of a unit and collects its symbols, along
+// with warnings about lines that assemble but likely do not do what was meant
+fn unit(
+    sources: &Sources,
+    ops: Vec<(SourceLine, Op)>,
+    options: &Options,
+) -> Result<(Object, Vec<Diagnostic>), Diagnostic> {
+    let mut section = Section::Text;
+    let mut instructions = Vec::new();
+    let mut warnings = Vec::new();
+    for (line, op) in ops {
+        if options.no_pseudo && op.is_pseudo() {
+            return Err(sources.line_error(
+                &line,
+                format!(
+                    "{:?} is a pseudo instruction, which --no-pseudo does not allow",
+                    line.text.trim()
+                ),
+            ));
+        }
+        match op {
+            Op::Section(new) => section = new,
+            Op::Words(_) if section == Section::Text => {
+                return Err(
+                    sources.line_error(&line, "data can only be placed in the .data section")
+                )
+            }
+            Op::Label(_)
+            | Op::Words(_)
+            | Op::Org(_)
+            | Op::Align(_)
+            | Op::Global(_)
+            | Op::Extern(_) => {}
+            _ if section == Section::Data => {
+                return Err(sources.line_error(
+                    &line,
+                    "instructions can only be placed in the .text section",
+                ))
+            }
+            _ => {}
+        }
+        warnings.extend(warning(sources, &line, &op));
+        instructions.push((line.location, op));
+    }
+
+    let mut instructions = labels::scope(sources, instructions)?;
+    if !options.case_sensitive {
+        labels::match_case(sources, &mut instructions)?;
+    }
+    Ok((Object::assemble(sources, instructions)?, warnings))
+}
+
+fn warning(sources: &Sources, line: &SourceLine, op: &Op) -> Option<Diagnostic> {
+    let Op::Branch(flag, true, _) = op else {
+        return None;
+    };
+    let flag = FLAG_NAMES[flag.0 as usize];
+    Some(sources.line_warning(
+        line,
+        format!(
+            "branching on {flag} being clear complements {flag}, \
+             it stays complemented after the branch"
+        ),
+    ))
+}
+
+pub fn to_verilog(name: &str, program: &Program) -> String {
+    if name.split_whitespace().count() != 1 {
+        panic!("invalid name: {name:?}");
+    }
+    let mut result: Vec<String> = vec![
+        format!("task {name};"),
+        "begin".to_string(),
+        format!("$display(\"{name}\");"),
+    ];
+    for (i, instruction) in program.instructions.iter().enumerate() {
+        result.push(format!(
+            "  instruction_mem[{i}] = {}",
+            instruction.to_verilog(program)
+        ));
+    }
+    for (i, word) in program.data.iter().enumerate() {
+        result.push(format!("  data_mem[{i}] = 16'd{word};"));
+    }
+    result.extend(vec!["end".into(), "endtask".into()]);
+
+    result.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(source: &str) -> Vec<String> {
+        match assemble(source, &Options::default()) {
+            Ok(_) => vec![],
+            Err(errors) => errors.into_iter().map(|err| err.message).collect(),
+        }
+    }
+
+    #[test]
+    fn warnings_are_returned() {
+        let program = assemble("main:\n  JNE main\n  HALT\n", &Options::default()).unwrap();
+        let [warning] = &program.warnings[..] else {
+            panic!("expected one warning, got {:?}", program.warnings);
+        };
+        assert!(warning
+            .message
+            .starts_with("WARNING: line 2: branching on EQ"));
+        assert_eq!(
+            (warning.file.as_deref(), warning.line),
+            (Some(SOURCE_NAME), Some(2))
+        );
+    }
+
+    #[test]
+    fn bit_index_out_of_range() {
+        assert!(errors("main:\n  SET R1.15\n  MOVB IN.15\n  HALT\n").is_empty());
+        assert_eq!(
+            errors("main:\n  SET R1.20\n  MOVB IN.40\n  HALT\n").len(),
+            2,
+            "both lines are rejected"
+        );
+    }
+
+    #[test]
+    fn binary_literals() {
+        let program = assemble(
+            ".data\n  .word 0b11, 0x10\n.text\nmain:\n  MOV R1, #0b10\n  HALT\n",
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(program.data, [3, 16]);
+        assert!(matches!(
+            program.instructions[..],
+            [Op::LBH(Reg(1), 0), Op::LBL(Reg(1), 2), Op::HALT]
+        ));
+    }
+
+    #[test]
+    fn parse_errors_give_the_line_and_macro() {
+        let source = ".macro bad reg\n  MOV \\reg, R9\n.endm\nmain:\n  bad R1\n  HALT\n";
+        let Err(errors) = assemble(source, &Options::default()) else {
+            panic!("expected an error");
+        };
+        let [error] = &errors[..] else {
+            panic!("expected one error, got {errors:?}");
+        };
+        assert!(
+            error
+                .message
+                .starts_with("ERR: line 5, in macro bad at line 2: Invalid register string"),
+            "{}",
+            error.message
+        );
+        assert!(error.message.ends_with(": MOV R1, R9"), "{}", error.message);
+        assert_eq!(
+            (error.file.as_deref(), error.line),
+            (Some(SOURCE_NAME), Some(5))
+        );
+    }
+
+    #[test]
+    fn errors_show_the_source_as_written() {
+        let errors = errors("main:\n  MOV\tR1, R9\n  FOO R1\n  MOV R1, #SIZE\n  HALT\n");
+        assert!(
+            errors[0].contains("Invalid register string R9"),
+            "{errors:?}"
+        );
+        assert!(errors[1].contains("invalid operator FOO"), "{errors:?}");
+        let errors = self::errors("main:\n  MOV R1, #SIZE\n  HALT\n");
+        assert!(
+            errors[0].contains("#SIZE is not a .equ constant or a label"),
+            "{errors:?}"
+        );
+    }
+}
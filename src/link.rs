@@ -0,0 +1,553 @@
+// Combines object files into one program. Each object's code keeps its order, so
+// the text of every object follows the text of the one before it in instruction
+// memory and the same for their data. Locals that collide with a name from another
+// object are renamed through the relocations, and the result is written back as an
+// object with every symbol resolved, which any command can then take.
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use crate::{
+    archive::Archive,
+    expr,
+    instruction::Op,
+    object::{Binding, Object},
+    preprocess::{Location, Sources},
+    program::{Options, Program, Section},
+    sim::DATA_MEM_SIZE,
+    types::ADDRESS_SPACE,
+    Diagnostic,
+};
+
+// where the sections go, from a linker script with one region per line:
+//
+//   rom 0x100 1024   # instruction memory: origin, length in words
+//   ram 0 512        # data memory
+pub struct Script {
+    pub name: String,
+    pub rom: Range<usize>,
+    pub ram: Range<usize>,
+    // line each region was given on, for the .org that places it
+    rom_line: usize,
+    ram_line: usize,
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Script {
+            name: String::new(),
+            rom: 0..ADDRESS_SPACE,
+            ram: 0..DATA_MEM_SIZE,
+            rom_line: 0,
+            ram_line: 0,
+        }
+    }
+}
+
+impl Script {
+    pub fn from_str(name: &str, text: &str) -> Result<Self, Diagnostic> {
+        let mut script = Script {
+            name: name.to_string(),
+            ..Script::default()
+        };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                Diagnostic::new(format!("ERR: {name}: line {}: {reason}: {line}", i + 1))
+                    .at(name, i + 1)
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [region, origin, length] = fields[..] else {
+                return Err(invalid("expected a region, origin and length"));
+            };
+            let value = |text: &str| match expr::eval(text, |_| None) {
+                Ok(value) if value >= 0 => Ok(value as usize),
+                Ok(_) => Err(invalid("negative value")),
+                Err(err) => Err(invalid(&err)),
+            };
+            let (origin, length) = (value(origin)?, value(length)?);
+            let (range, size) = match region.to_lowercase().as_str() {
+                "rom" => {
+                    script.rom_line = i + 1;
+                    (&mut script.rom, ADDRESS_SPACE)
+                }
+                "ram" => {
+                    script.ram_line = i + 1;
+                    (&mut script.ram, DATA_MEM_SIZE)
+                }
+                _ => return Err(invalid("unknown region, expected rom or ram")),
+            };
+            if origin + length > size {
+                return Err(invalid(&format!(
+                    "the region ends past the {size} word memory"
+                )));
+            }
+            *range = origin..origin + length;
+        }
+        Ok(script)
+    }
+}
+
+// the locals of each object renamed so every name is defined once, keeping the
+// first object's name
+fn rename_locals(objects: &mut [(String, Object)]) -> Result<(), Diagnostic> {
+    let globals: HashSet<String> = objects
+        .iter()
+        .flat_map(|(_, object)| object.symbols.iter())
+        .filter(|symbol| symbol.binding == Binding::Global)
+        .map(|symbol| symbol.name.clone())
+        .collect();
+    let mut taken: HashSet<String> = objects
+        .iter()
+        .flat_map(|(_, object)| object.symbols.iter())
+        .map(|symbol| symbol.name.clone())
+        .collect();
+    let mut seen: HashSet<String> = HashSet::new();
+    for (i, (file, object)) in objects.iter_mut().enumerate() {
+        let mut renames: HashMap<String, String> = HashMap::new();
+        for symbol in &mut object.symbols {
+            if symbol.binding == Binding::Global {
+                continue;
+            }
+            if globals.contains(&symbol.name) || !seen.insert(symbol.name.clone()) {
+                let name = (i..)
+                    .map(|n| format!("{}_{n}", symbol.name))
+                    .find(|name| !taken.contains(name))
+                    .unwrap();
+                taken.insert(name.clone());
+                renames.insert(symbol.name.clone(), name.clone());
+                symbol.name = name;
+            }
+        }
+        rename(file, object, &renames)?;
+    }
+    Ok(())
+}
+
+// renames symbols where they are defined, declared and referred to
+fn rename(
+    file: &str,
+    object: &mut Object,
+    renames: &HashMap<String, String>,
+) -> Result<(), Diagnostic> {
+    for relocation in &mut object.relocations {
+        if let Some(name) = renames.get(&relocation.symbol) {
+            let symbol = object
+                .code
+                .get_mut(relocation.index)
+                .and_then(|(_, op)| op.symbol())
+                .ok_or_else(|| {
+                    Diagnostic::new(format!(
+                        "ERR: {file}: the relocation of {} is not at an instruction with a symbol",
+                        relocation.symbol
+                    ))
+                })?;
+            *symbol = name.clone();
+            relocation.symbol = name.clone();
+        }
+    }
+    for (_, op) in &mut object.code {
+        if let Op::Label(label) = op {
+            if let Some(name) = renames.get(label) {
+                *label = name.clone();
+            }
+        }
+    }
+    for name in &mut object.externs {
+        if let Some(new) = renames.get(name) {
+            name.clone_from(new);
+        }
+    }
+    Ok(())
+}
+
+// the name symbols are told apart by, see Options::case_sensitive
+fn key(name: &str, case_sensitive: bool) -> String {
+    match case_sensitive {
+        true => name.to_string(),
+        false => name.to_lowercase(),
+    }
+}
+
+// every .extern spelled as the .global it refers to, when they differ in case
+fn match_case(objects: &mut [(String, Object)]) -> Result<(), Diagnostic> {
+    let mut spellings: HashMap<String, String> = HashMap::new();
+    for (_, object) in objects.iter() {
+        for symbol in &object.symbols {
+            if symbol.binding == Binding::Global {
+                spellings
+                    .entry(symbol.name.to_lowercase())
+                    .or_insert_with(|| symbol.name.clone());
+            }
+        }
+    }
+    for (file, object) in objects {
+        let renames: HashMap<String, String> = object
+            .externs
+            .iter()
+            .filter_map(|name| {
+                let spelling = spellings.get(&name.to_lowercase())?;
+                (spelling != name).then(|| (name.clone(), spelling.clone()))
+            })
+            .collect();
+        rename(file, object, &renames)?;
+    }
+    Ok(())
+}
+
+// checks every .extern is defined .global by exactly one object
+fn resolve(objects: &[(String, Object)], case_sensitive: bool) -> Result<(), Diagnostic> {
+    let mut defined: HashMap<String, &str> = HashMap::new();
+    for (file, object) in objects {
+        for symbol in &object.symbols {
+            if symbol.binding == Binding::Global {
+                if let Some(first) = defined.insert(key(&symbol.name, case_sensitive), file) {
+                    return Err(Diagnostic::new(format!(
+                        "ERR: {} is defined .global in both {first} and {file}",
+                        symbol.name
+                    )));
+                }
+            }
+        }
+    }
+    for (file, object) in objects {
+        for name in &object.externs {
+            if defined.contains_key(&key(name, case_sensitive)) {
+                continue;
+            }
+            let local = objects
+                .iter()
+                .find(|(_, other)| other.symbols.iter().any(|symbol| &symbol.name == name));
+            match local {
+                Some((other, _)) => {
+                    return Err(Diagnostic::new(format!(
+                        "ERR: {name} is declared .extern in {file} but is local to {other}, \
+                         declare it .global there"
+                    )))
+                }
+                None => {
+                    return Err(Diagnostic::new(format!(
+                        "ERR: {name} is declared .extern in {file} but no object defines it"
+                    )))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// adds the archive members defining a symbol the objects leave undefined, until no
+// archive defines any that are left. Archives are searched in the order given.
+pub fn pull(
+    objects: &mut Vec<(String, Object)>,
+    mut archives: Vec<(String, Archive)>,
+    case_sensitive: bool,
+) -> Result<(), Diagnostic> {
+    // (archive, member) in the order they are needed
+    let mut pulled: Vec<(usize, usize)> = Vec::new();
+    loop {
+        let included: Vec<&Object> = objects
+            .iter()
+            .map(|(_, object)| object)
+            .chain(pulled.iter().map(|(a, m)| &archives[*a].1.members[*m].1))
+            .collect();
+        let defined: HashSet<String> = included
+            .iter()
+            .flat_map(|object| object.symbols.iter())
+            .filter(|symbol| symbol.binding == Binding::Global)
+            .map(|symbol| key(&symbol.name, case_sensitive))
+            .collect();
+        let needed = included
+            .iter()
+            .flat_map(|object| object.externs.iter())
+            .map(|name| key(name, case_sensitive))
+            .filter(|name| !defined.contains(name))
+            .find_map(|name| {
+                archives.iter().enumerate().find_map(|(a, (_, archive))| {
+                    archive
+                        .index
+                        .iter()
+                        .find(|(symbol, _)| key(symbol, case_sensitive) == name)
+                        .map(|(symbol, m)| (a, *m, symbol))
+                })
+            });
+        let Some((a, m, symbol)) = needed else {
+            break;
+        };
+        // the index is public, so it may not agree with the members. A member that
+        // does not define the symbol would be pulled again and again.
+        let defines = archives[a].1.members.get(m).is_some_and(|(_, member)| {
+            member.symbols.iter().any(|defined| {
+                defined.binding == Binding::Global
+                    && key(&defined.name, case_sensitive) == key(symbol, case_sensitive)
+            })
+        });
+        if !defines {
+            return Err(Diagnostic::new(format!(
+                "ERR: {}: the index gives {symbol} in a member which does not define it",
+                archives[a].0
+            )));
+        }
+        pulled.push((a, m));
+    }
+    // members are moved out from the back, so the indexes of the rest stay valid
+    let mut order = pulled.clone();
+    order.sort_unstable();
+    let mut taken: HashMap<(usize, usize), (String, Object)> = HashMap::new();
+    for (a, m) in order.into_iter().rev() {
+        let (member, object) = archives[a].1.members.remove(m);
+        taken.insert((a, m), (format!("{}({member})", archives[a].0), object));
+    }
+    // each member is pulled once, as it then defines the symbol it was pulled for
+    objects.extend(pulled.iter().filter_map(|key| taken.remove(key)));
+    Ok(())
+}
+
+// the linked program, as an object with nothing left to resolve along with the
+// program it lays out to
+pub fn link(
+    mut objects: Vec<(String, Object)>,
+    script: &Script,
+    options: &Options,
+) -> Result<(Object, Program), Diagnostic> {
+    if !options.case_sensitive {
+        match_case(&mut objects)?;
+    }
+    resolve(&objects, options.case_sensitive)?;
+    let globals: HashSet<String> = objects
+        .iter()
+        .flat_map(|(_, object)| object.symbols.iter())
+        .filter(|symbol| symbol.binding == Binding::Global)
+        .map(|symbol| symbol.name.clone())
+        .collect();
+    rename_locals(&mut objects)?;
+
+    let mut files: Vec<String> = Vec::new();
+    let mut code: Vec<(Location, Op)> = Vec::new();
+    for (_, object) in objects {
+        let offset = files.len();
+        files.extend(object.files);
+        if let Some((location, _)) = object.code.first() {
+            // each object starts in .text, as its source did
+            code.push((
+                Location {
+                    file: location.file + offset,
+                    line: location.line,
+                },
+                Op::Section(Section::Text),
+            ));
+        }
+        code.extend(object.code.into_iter().map(|(location, op)| {
+            let location = Location {
+                file: location.file + offset,
+                ..location
+            };
+            (location, op)
+        }));
+    }
+    // the script is the last source, so the .org placing each region is listed there
+    if !script.name.is_empty() {
+        let file = files.len();
+        files.push(script.name.clone());
+        let mut start = Vec::new();
+        if script.ram.start > 0 {
+            let location = Location {
+                file,
+                line: script.ram_line,
+            };
+            start.push((location, Op::Section(Section::Data)));
+            start.push((location, Op::Org(script.ram.start as u16)));
+        }
+        if script.rom.start > 0 {
+            let location = Location {
+                file,
+                line: script.rom_line,
+            };
+            start.push((location, Op::Section(Section::Text)));
+            start.push((location, Op::Org(script.rom.start as u16)));
+        }
+        code.splice(0..0, start);
+    }
+
+    let sources = Sources::from_names(&files);
+    let mut object = Object::assemble(&sources, code)?;
+    for symbol in &mut object.symbols {
+        if globals.contains(&symbol.name) {
+            symbol.binding = Binding::Global;
+        }
+    }
+
+    if !script.name.is_empty() {
+        object.rom = Some(script.rom.clone());
+        object.ram = Some(script.ram.clone());
+    }
+    // lay the program out once to check it fits the regions
+    let program = program(&sources, &object, options)?;
+    Ok((object, program))
+}
+
+// the program an object lays out to, within the regions it was linked for if any
+pub fn program(
+    sources: &Sources,
+    object: &Object,
+    options: &Options,
+) -> Result<Program, Diagnostic> {
+    let mem_size = match &object.rom {
+        Some(rom) => Some(options.mem_size.map_or(rom.end, |size| size.min(rom.end))),
+        None => options.mem_size,
+    };
+    let options = Options {
+        mem_size,
+        ..options.clone()
+    };
+    let mut program = Program::new(sources, object.code.clone(), &options)?;
+    if let Some(ram) = &object.ram {
+        if program.data.len() > ram.end {
+            return Err(Diagnostic::new(format!(
+                "ERR: the data takes {} words, past the end of the RAM region at {}",
+                program.data.len(),
+                ram.end
+            )));
+        }
+    }
+    if let Some(rom) = &object.rom {
+        program.origin = rom.start;
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocess::preprocess_str;
+
+    fn object(name: &str, source: &str) -> (String, Object) {
+        let options = Options::default();
+        let (sources, lines) = preprocess_str(name, source, &options).unwrap();
+        let ops = lines
+            .into_iter()
+            .map(|line| {
+                let op = crate::parse(&sources, &line).unwrap();
+                (line, op)
+            })
+            .collect();
+        let (object, _) = crate::unit(&sources, ops, &options).unwrap();
+        (name.replace(".asm", ".o"), object)
+    }
+
+    fn branches(program: &Program) -> Vec<&str> {
+        program
+            .instructions
+            .iter()
+            .filter_map(|op| match op {
+                Op::LOADBR(label) => Some(label.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pull_takes_only_needed_members() {
+        let mut objects = vec![object(
+            "main.asm",
+            ".extern div\nmain:\n  LOADBR div\n  HALT\n",
+        )];
+        let members = vec![
+            object("bcd.asm", ".global bcd\nbcd:\n  HALT\n"),
+            object(
+                "div.asm",
+                ".global div\n.extern sub16\ndiv:\n  LOADBR sub16\n  HALT\n",
+            ),
+            object("sub.asm", ".global sub16\nsub16:\n  HALT\n"),
+        ];
+        let archive = Archive::new("math.a", members).unwrap();
+        pull(&mut objects, vec![("math.a".to_string(), archive)], false).unwrap();
+        let names: Vec<&str> = objects.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["main.o", "math.a(div.o)", "math.a(sub.o)"]);
+    }
+
+    #[test]
+    fn archives_that_disagree_are_errors() {
+        let members = vec![
+            object("bcd.asm", ".global bcd\nbcd:\n  HALT\n"),
+            object("div.asm", ".global bcd\nbcd:\n  HALT\n"),
+        ];
+        let Err(error) = Archive::new("math.a", members) else {
+            panic!("expected an error");
+        };
+        assert_eq!(
+            error.message,
+            "ERR: math.a: bcd is defined .global in both bcd.o and div.o"
+        );
+
+        let mut objects = vec![object("main.asm", ".extern div\nmain:\n  HALT\n")];
+        let members = vec![object("bcd.asm", ".global bcd\nbcd:\n  HALT\n")];
+        let mut archive = Archive::new("math.a", members).unwrap();
+        archive.index.insert("div".to_string(), 0);
+        let error = pull(&mut objects, vec![("math.a".to_string(), archive)], false).unwrap_err();
+        assert_eq!(
+            error.message,
+            "ERR: math.a: the index gives div in a member which does not define it"
+        );
+    }
+
+    #[test]
+    fn clashing_locals_are_renamed() {
+        let objects = vec![
+            object(
+                "a.asm",
+                ".extern helper\nstart:\nloop:\n  LOADBR loop\n  LOADBR helper\n  HALT\n",
+            ),
+            object(
+                "b.asm",
+                ".global helper\nhelper:\nloop:\n  LOADBR loop\n  HALT\n",
+            ),
+            // a local with the name of a global elsewhere
+            object("c.asm", "helper:\n  LOADBR helper\n  HALT\n"),
+        ];
+        let (_, program) = link(objects, &Script::default(), &Options::default()).unwrap();
+        assert_eq!(branches(&program), ["loop", "helper", "loop_1", "helper_2"]);
+        let address = |label: &str| program.labels[label].0;
+        assert_eq!(
+            [
+                address("loop"),
+                address("helper"),
+                address("loop_1"),
+                address("helper_2")
+            ],
+            [0, 3, 3, 5]
+        );
+    }
+
+    #[test]
+    fn malformed_scripts_are_errors() {
+        let error = |text: &str| match Script::from_str("map.ld", text) {
+            Ok(_) => panic!("expected an error for {text:?}"),
+            Err(err) => err.message,
+        };
+        assert!(error("rom 0x100\n").starts_with("ERR: map.ld: line 1: expected a region"));
+        assert!(error("flash 0 16\n").contains("unknown region"));
+        assert!(error("# regions\nram 0 -1\n").starts_with("ERR: map.ld: line 2: negative"));
+        assert!(error("rom 0x700 0x200\n").contains("ends past the 2048 word memory"));
+    }
+
+    #[test]
+    fn relocations_outside_the_code_are_errors() {
+        let mut objects = vec![
+            object("a.asm", "loop:\n  LOADBR loop\n  HALT\n"),
+            object("b.asm", "loop:\n  LOADBR loop\n  HALT\n"),
+        ];
+        objects[1].1.relocations[0].index = 10;
+        let Err(error) = link(objects, &Script::default(), &Options::default()) else {
+            panic!("expected an error");
+        };
+        assert_eq!(
+            error.message,
+            "ERR: b.o: the relocation of loop is not at an instruction with a symbol"
+        );
+    }
+}
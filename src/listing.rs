@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    preprocess::{Location, Sources},
+    program::Program,
+};
+
+// data words shown on each row of the listing
+const WORDS_PER_ROW: usize = 3;
+
+// the source next to the instructions each line assembled to, so the expansion of
+// pseudo instructions can be checked. Data words are shown with a `d` before their
+// data memory address.
+pub fn to_listing(sources: &Sources, program: &Program) -> String {
+    // (address, contents) rows for each line
+    let mut per_line: BTreeMap<Location, Vec<(String, String)>> = BTreeMap::new();
+    for (address, line) in program.lines.iter().enumerate() {
+        per_line
+            .entry(*line)
+            .or_default()
+            .push((address.to_string(), program.instructions[address].to_asm()));
+    }
+    let mut data_lines: BTreeMap<Location, Vec<usize>> = BTreeMap::new();
+    for (address, line) in program.data_lines.iter().enumerate() {
+        data_lines.entry(*line).or_default().push(address);
+    }
+    for (line, addresses) in data_lines {
+        for row in addresses.chunks(WORDS_PER_ROW) {
+            let words: Vec<String> = row
+                .iter()
+                .map(|address| format!("{:#06x}", program.data[*address]))
+                .collect();
+            per_line
+                .entry(line)
+                .or_default()
+                .push((format!("d{}", row[0]), words.join(" ")));
+        }
+    }
+
+    let mut result = vec![format!(
+        "{:>5}  {:<24}{:>5} | source",
+        "addr", "instruction", "line"
+    )];
+    for (file, source) in sources.files.iter().enumerate() {
+        if file > 0 {
+            result.push(format!("\n{:36} | ; {}", "", source.name));
+        }
+        for (i, text) in source.lines.iter().enumerate() {
+            let line = i + 1;
+            let rows = per_line
+                .get(&Location { file, line })
+                .map_or(&[][..], Vec::as_slice);
+            if rows.is_empty() {
+                result.push(format!("{:31}{line:>5} | {text}", ""));
+            }
+            for (j, (address, instruction)) in rows.iter().enumerate() {
+                if j == 0 {
+                    result.push(format!("{address:>5}  {instruction:<24}{line:>5} | {text}"));
+                } else {
+                    result.push(format!("{address:>5}  {instruction:<24}{:>5} |", ""));
+                }
+            }
+        }
+    }
+    result.join("\n")
+}
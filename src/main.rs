@@ -1,63 +1,330 @@
-mod instruction;
-mod parse_file;
-mod types;
 use std::{
-    collections::HashMap,
-    env::{self, args},
-    fs,
+    env, fs,
+    path::{Path, PathBuf},
 };
 
-use instruction::Op;
-use parse_file::Line;
-use types::{Address, Reg};
+use assembler::{
+    archive::Archive,
+    coverage, debugger, expr, gdb, link, listing,
+    object::Object,
+    parse_file::{self, Line},
+    preprocess::Sources,
+    profile, read_object, sim, testbench, timing, to_verilog, Diagnostic, Op, Options, Program,
+};
+
+// flags that are followed by a value
+const VALUE_FLAGS: [&str; 9] = [
+    "--table",
+    "--max-steps",
+    "-I",
+    "-D",
+    "--fill",
+    "--mem-size",
+    "-o",
+    "--script",
+    "--emit",
+];
+
+// command line arguments, split into positional arguments and flags
+struct Cli {
+    args: Vec<String>,
+    flags: Vec<(String, Option<String>)>,
+}
+
+impl Cli {
+    fn parse(mut args: impl Iterator<Item = String>) -> Self {
+        let mut cli = Cli {
+            args: Vec::new(),
+            flags: Vec::new(),
+        };
+        while let Some(arg) = args.next() {
+            if VALUE_FLAGS.contains(&arg.as_str()) {
+                let value = args
+                    .next()
+                    .unwrap_or_else(|| panic!("CLI ERR: no value given for {arg}"));
+                cli.flags.push((arg, Some(value)));
+            } else if let Some(flag) = ["-I", "-D"].into_iter().find(|flag| arg.starts_with(flag)) {
+                // -Ipath and -DNAME=value, as with C compilers
+                cli.flags
+                    .push((flag.to_string(), Some(arg[2..].to_string())));
+            } else if arg.starts_with('-') && arg != "-" {
+                cli.flags.push((arg, None));
+            } else {
+                cli.args.push(arg);
+            }
+        }
+        cli
+    }
+
+    fn arg(&self, i: usize) -> Option<&str> {
+        self.args.get(i).map(String::as_str)
+    }
+
+    fn file(&self, i: usize) -> &str {
+        self.arg(i).expect("CLI ERR: no argument given for file")
+    }
+
+    fn flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|(name, _)| name == flag)
+    }
+
+    fn value(&self, flag: &str) -> Option<&str> {
+        self.flags
+            .iter()
+            .find(|(name, _)| name == flag)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    // every value given for a flag that can be repeated
+    fn values(&self, flag: &str) -> Vec<&str> {
+        self.flags
+            .iter()
+            .filter(|(name, _)| name == flag)
+            .filter_map(|(_, value)| value.as_deref())
+            .collect()
+    }
+
+    fn max_steps(&self) -> u64 {
+        self.value("--max-steps")
+            .map_or(sim::DEFAULT_MAX_STEPS, |steps| {
+                steps.parse().expect("CLI ERR: invalid --max-steps")
+            })
+    }
+
+    fn options(&self) -> Options {
+        Options {
+            optimize_immediates: self.flag("--optimize-immediates"),
+            no_pseudo: self.flag("--no-pseudo"),
+            case_sensitive: self.flag("--case-sensitive"),
+            include_paths: self.values("-I").into_iter().map(PathBuf::from).collect(),
+            defines: self.values("-D").into_iter().map(define).collect(),
+            fill: self.value("--fill").map(fill),
+            mem_size: self.value("--mem-size").map(|size| {
+                parse_file::parse_number(size).expect("CLI ERR: invalid --mem-size") as usize
+            }),
+        }
+    }
+}
+
+// NAME=value, or NAME alone for 1
+fn define(arg: &str) -> (String, i64) {
+    let (name, value) = arg.split_once('=').unwrap_or((arg, "1"));
+    let value = expr::eval(value, |_| None)
+        .unwrap_or_else(|err| panic!("CLI ERR: invalid value for -D {name}: {err}"));
+    (name.trim().to_string(), value)
+}
+
+// the value, or the error reported like every other error of the command line
+fn check<T>(result: Result<T, Diagnostic>) -> T {
+    result.unwrap_or_else(|err| panic!("{}", err.message))
+}
+
+fn print_warnings(warnings: &[Diagnostic]) {
+    for warning in warnings {
+        eprintln!("{}", warning.message);
+    }
+}
+
+// a single machine instruction for --fill
+fn fill(instruction: &str) -> Op {
+    let mut ops = Line::from_str(instruction)
+        .and_then(Line::to_instruction)
+        .unwrap_or_else(|err| panic!("CLI ERR: invalid --fill {instruction:?}: {}", err.message))
+        .unpack();
+    match (ops.pop(), ops.is_empty()) {
+        (Some(op), true) if !op.is_directive() && !matches!(op, Op::LOADBR(_)) => op,
+        _ => panic!("CLI ERR: --fill must be a single instruction"),
+    }
+}
 
 fn main() {
-    let mut args = env::args();
-    _ = args.next();
-    let file_name = args.next().expect("CLI ERR: no argument given for file");
-    let name = args.next().unwrap_or(file_name.clone());
-
-    let instructions: Vec<_> = fs::read_to_string(file_name)
-        .expect("CLI ERR: could not open file")
-        .lines()
-        .filter(|line| !line.trim().is_empty())
-        .map(|line| Line::from_str(line).to_instruction())
-        .collect();
-
-    print!("{}", to_verilog(&name, instructions))
-}
-
-pub fn to_verilog(name: &str, instructions: Vec<Op>) -> String {
-    if name.split_whitespace().count() != 1 {
-        panic!("invalid name: {name:?}");
-    }
-    let mut labels: HashMap<String, Address> = HashMap::new();
-    let mut i = 0;
-    let mut final_instructions = Vec::new();
-    for instruction in instructions
-        .into_iter()
-        .flat_map(|op: Op| op.unpack())
-        .collect::<Vec<Op>>()
-    {
-        if let Op::Label(label) = instruction {
-            labels.insert(label, Address(i));
-        } else {
-            final_instructions.push(instruction);
-            i += 1;
-        }
-    }
-    let mut result: Vec<String> = vec![
-        format!("task {name};"),
-        "begin".to_string(),
-        format!("$display(\"{name}\");"),
-    ];
-    for (i, instruction) in final_instructions.iter().enumerate() {
-        result.push(format!(
-            "  instruction_mem[{i}] = {}",
-            instruction.to_verilog(&labels)
-        ));
-    }
-    result.extend(vec!["end".into(), "endtask".into()]);
-
-    result.join("\n")
+    let cli = Cli::parse(env::args().skip(1));
+    let options = cli.options();
+
+    if cli.flag("-c") {
+        let file_name = cli.file(0);
+        let output = cli
+            .value("-o")
+            .map_or_else(|| Path::new(file_name).with_extension("o"), PathBuf::from);
+        let (_, object, warnings) = check(read_object(file_name, &options));
+        print_warnings(&warnings);
+        fs::write(&output, object.to_text()).expect("CLI ERR: could not write object file");
+        return;
+    }
+
+    match cli.file(0) {
+        "link" => {
+            if cli.args.len() < 2 {
+                panic!("CLI ERR: no object files given to link");
+            }
+            let mut objects = Vec::new();
+            let mut archives = Vec::new();
+            for file_name in &cli.args[1..] {
+                let text = fs::read_to_string(file_name)
+                    .unwrap_or_else(|_| panic!("CLI ERR: could not open {file_name}"));
+                if file_name.ends_with(".a") {
+                    archives.push((
+                        file_name.clone(),
+                        check(Archive::from_text(file_name, &text)),
+                    ));
+                } else {
+                    objects.push((
+                        file_name.clone(),
+                        check(Object::from_text(file_name, &text)),
+                    ));
+                }
+            }
+            check(link::pull(&mut objects, archives, options.case_sensitive));
+            let script = match cli.value("--script") {
+                Some(script) => check(link::Script::from_str(
+                    script,
+                    &fs::read_to_string(script).expect("CLI ERR: could not open linker script"),
+                )),
+                None => link::Script::default(),
+            };
+            let (object, program) = check(link::link(objects, &script, &options));
+            match cli.value("--emit").unwrap_or("object") {
+                "object" => {
+                    let output = cli.value("-o").unwrap_or("linked.o");
+                    eprintln!("{output}: {}", program.size_summary());
+                    fs::write(output, object.to_text())
+                        .expect("CLI ERR: could not write object file");
+                }
+                format => {
+                    let output = cli.value("-o");
+                    // the task is named after the output file
+                    let name = output.map_or("linked", |output| {
+                        Path::new(output)
+                            .file_stem()
+                            .and_then(|stem| stem.to_str())
+                            .unwrap_or(output)
+                    });
+                    eprintln!("{name}: {}", program.size_summary());
+                    let sources = Sources::from_names(&program.files);
+                    let text = emit(format, name, &sources, &program, cli.max_steps());
+                    match output {
+                        Some(output) => {
+                            fs::write(output, text).expect("CLI ERR: could not write output")
+                        }
+                        None => print!("{text}"),
+                    }
+                }
+            }
+        }
+        "archive" => {
+            let name = cli.file(1);
+            let members = cli.args[2..]
+                .iter()
+                .map(|file_name| {
+                    let text = fs::read_to_string(file_name)
+                        .unwrap_or_else(|_| panic!("CLI ERR: could not open {file_name}"));
+                    (
+                        file_name.clone(),
+                        check(Object::from_text(file_name, &text)),
+                    )
+                })
+                .collect();
+            let archive = check(Archive::new(name, members));
+            fs::write(name, archive.to_text()).expect("CLI ERR: could not write archive");
+        }
+        "debug" => {
+            let (source, program) = read_program(cli.file(1), &options);
+            debugger::run(&source, &program);
+        }
+        "gdb" => {
+            let (_, program) = read_program(cli.file(1), &options);
+            match cli.arg(2) {
+                Some("-") => gdb::serve(gdb::Stdio, &program),
+                port => {
+                    let port =
+                        port.map_or(1234, |port| port.parse().expect("CLI ERR: invalid port"));
+                    gdb::listen(port, &program);
+                }
+            }
+        }
+        "timing" => {
+            let (_, program) = read_program(cli.file(1), &options);
+            let table = match cli.value("--table") {
+                Some(table) => timing::TimingTable::from_str(
+                    &fs::read_to_string(table).expect("CLI ERR: could not open timing table"),
+                ),
+                None => timing::TimingTable::default(),
+            };
+            timing::measure(&program, &table, cli.max_steps()).print(&program);
+        }
+        "profile" => {
+            let (source, program) = read_program(cli.file(1), &options);
+            profile::profile(&program, cli.max_steps()).print(&source, &program);
+        }
+        "coverage" => {
+            let (source, program) = read_program(cli.file(1), &options);
+            let coverage = coverage::coverage(&program, cli.max_steps());
+            if cli.flag("--lcov") {
+                coverage.print_lcov(&source, &program);
+            } else {
+                coverage.print_text(&source, &program);
+            }
+        }
+        "testbench" => {
+            let file_name = cli.file(1);
+            let name = cli.arg(2).unwrap_or(file_name);
+            let (source, program) = read_program(file_name, &options);
+            print!(
+                "{}",
+                emit("testbench", name, &source, &program, cli.max_steps())
+            );
+        }
+        "listing" => {
+            let file_name = cli.file(1);
+            let (source, program) = read_program(file_name, &options);
+            print!(
+                "{}",
+                emit("listing", file_name, &source, &program, cli.max_steps())
+            );
+        }
+        file_name => {
+            let name = cli.arg(1).unwrap_or(file_name);
+            let (source, program) = read_program(file_name, &options);
+            print!(
+                "{}",
+                emit("verilog", name, &source, &program, cli.max_steps())
+            );
+        }
+    }
+}
+
+// the program in one of the formats the commands print, name is the task name
+fn emit(format: &str, name: &str, sources: &Sources, program: &Program, max_steps: u64) -> String {
+    match format {
+        "verilog" => to_verilog(name, program),
+        "listing" => listing::to_listing(sources, program) + "\n",
+        "testbench" => format!(
+            "{}\n\n{}",
+            to_verilog(name, program),
+            testbench::to_testbench(name, program, max_steps)
+        ),
+        _ => panic!(
+            "CLI ERR: unknown --emit format {format}, expected object, verilog, listing or testbench"
+        ),
+    }
+}
+
+// returns the source files read along with the assembled program, from either
+// source or an object file
+fn read_program(file_name: &str, options: &Options) -> (Sources, Program) {
+    let (sources, object) = if file_name.ends_with(".o") {
+        let text = fs::read_to_string(file_name).expect("CLI ERR: could not open file");
+        let object = check(Object::from_text(file_name, &text));
+        (Sources::from_names(&object.files), object)
+    } else {
+        let (sources, object, warnings) = check(read_object(file_name, options));
+        print_warnings(&warnings);
+        (sources, object)
+    };
+    if let Some(name) = object.externs.first() {
+        panic!("ERR: {name} is declared .extern, link {file_name} with the object defining it");
+    }
+    let program = check(link::program(&sources, &object, options));
+    eprintln!("{file_name}: {}", program.size_summary());
+    (sources, program)
 }
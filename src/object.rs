@@ -0,0 +1,508 @@
+// Relocatable object files, written by `-c` and combined by the linker.
+//
+// Instruction addresses are only known once the whole program is put together: RET
+// dispatches over every CALL in the program and immediate loads can be shortened. So
+// objects keep the instructions of a unit as the parser left them, written back as
+// source lines, along with the symbols it defines and a relocation for every
+// instruction that refers to a symbol:
+//
+//   MINI-RISC object 1
+//   file 0 main.asm
+//   symbol global text main
+//   symbol local data buffer
+//   extern print
+//   reloc 4 print
+//   code 0:3 CALL print
+//
+// Code lines give the file and line each instruction came from.
+use std::{collections::HashMap, ops::Range};
+
+use crate::{
+    instruction::Op,
+    parse_file::Line,
+    preprocess::{Location, Sources},
+    program::Section,
+    Diagnostic,
+};
+
+const HEADER: &str = "MINI-RISC object 1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Local,
+    Global,
+}
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Section,
+    pub binding: Binding,
+}
+
+// the instruction at index refers to symbol
+#[derive(Clone, Debug)]
+pub struct Relocation {
+    pub index: usize,
+    pub symbol: String,
+}
+
+pub struct Object {
+    // source files the code came from, indexed by the file of each location
+    pub files: Vec<String>,
+    pub code: Vec<(Location, Op)>,
+    pub symbols: Vec<Symbol>,
+    pub externs: Vec<String>,
+    pub relocations: Vec<Relocation>,
+    // the regions of the linker script a linked object was laid out for, see link::Script
+    pub rom: Option<Range<usize>>,
+    pub ram: Option<Range<usize>>,
+}
+
+impl Object {
+    // collects the symbols of a unit, taking .global and .extern out of its code
+    pub fn assemble(sources: &Sources, ops: Vec<(Location, Op)>) -> Result<Self, Diagnostic> {
+        let describe = |location: Location| sources.describe(location);
+        let mut object = Object {
+            files: sources.files.iter().map(|file| file.name.clone()).collect(),
+            code: Vec::new(),
+            symbols: Vec::new(),
+            externs: Vec::new(),
+            relocations: Vec::new(),
+            rom: None,
+            ram: None,
+        };
+        let mut globals: Vec<(Location, String)> = Vec::new();
+        let mut defined: HashMap<String, Location> = HashMap::new();
+        let mut section = Section::Text;
+        for (location, op) in ops {
+            match op {
+                Op::Global(names) => globals.extend(names.into_iter().map(|name| (location, name))),
+                Op::Extern(names) => object.externs.extend(names),
+                op => {
+                    match &op {
+                        Op::Section(new) => section = *new,
+                        Op::Label(name) => {
+                            if let Some(first) = defined.insert(name.clone(), location) {
+                                return Err(sources.error(
+                                    location,
+                                    format!(
+                                        "label {name:?} is already defined at {}",
+                                        describe(first)
+                                    ),
+                                ));
+                            }
+                            object.symbols.push(Symbol {
+                                name: name.clone(),
+                                section,
+                                binding: Binding::Local,
+                            });
+                        }
+                        _ => {}
+                    }
+                    object.code.push((location, op));
+                }
+            }
+        }
+        for (location, name) in globals {
+            match object.symbols.iter_mut().find(|symbol| symbol.name == name) {
+                Some(symbol) => symbol.binding = Binding::Global,
+                None => {
+                    return Err(sources.error(
+                        location,
+                        format!(".global {name} is not defined in this file"),
+                    ))
+                }
+            }
+        }
+        for name in &object.externs {
+            if let Some(location) = defined.get(name) {
+                return Err(sources.error(
+                    *location,
+                    format!("{name} is declared .extern but defined here"),
+                ));
+            }
+        }
+        for (index, (location, op)) in object.code.iter_mut().enumerate() {
+            // an immediate, which is as likely to be a missing .equ
+            let immediate = matches!(op, Op::LoadLabel(_, _));
+            if let Some(symbol) = op.symbol() {
+                if !defined.contains_key(symbol) && !object.externs.contains(symbol) {
+                    let message = match immediate {
+                        true => format!(
+                            "#{symbol} is not a .equ constant or a label, declare it with \
+                             .extern if it is a label in another file"
+                        ),
+                        false => format!(
+                            "{symbol} is not defined, declare it with .extern if it is \
+                             in another file"
+                        ),
+                    };
+                    return Err(sources.error(*location, message));
+                }
+                object.relocations.push(Relocation {
+                    index,
+                    symbol: symbol.clone(),
+                });
+            }
+        }
+        Ok(object)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![HEADER.to_string()];
+        for (i, file) in self.files.iter().enumerate() {
+            lines.push(format!("file {i} {file}"));
+        }
+        for (region, range) in [("rom", &self.rom), ("ram", &self.ram)] {
+            if let Some(range) = range {
+                lines.push(format!("{region} {} {}", range.start, range.len()));
+            }
+        }
+        for symbol in &self.symbols {
+            let binding = match symbol.binding {
+                Binding::Local => "local",
+                Binding::Global => "global",
+            };
+            let section = match symbol.section {
+                Section::Text => "text",
+                Section::Data => "data",
+            };
+            lines.push(format!("symbol {binding} {section} {}", symbol.name));
+        }
+        for name in &self.externs {
+            lines.push(format!("extern {name}"));
+        }
+        for relocation in &self.relocations {
+            lines.push(format!("reloc {} {}", relocation.index, relocation.symbol));
+        }
+        for (location, op) in &self.code {
+            lines.push(format!(
+                "code {}:{} {}",
+                location.file,
+                location.line,
+                op.to_source()
+            ));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    // name is the object file, for errors
+    pub fn from_text(name: &str, text: &str) -> Result<Self, Diagnostic> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(HEADER) {
+            return Err(Diagnostic::new(format!(
+                "ERR: {name} is not an object file"
+            )));
+        }
+        let mut object = Object {
+            files: Vec::new(),
+            code: Vec::new(),
+            symbols: Vec::new(),
+            externs: Vec::new(),
+            relocations: Vec::new(),
+            rom: None,
+            ram: None,
+        };
+        for (i, line) in lines {
+            let error = |message: String| {
+                Diagnostic::new(format!("ERR: {name}: line {}: {message}", i + 1)).at(name, i + 1)
+            };
+            let invalid = || error(format!("invalid object line: {line}"));
+            let (kind, rest) = line.split_once(' ').ok_or_else(invalid)?;
+            match kind {
+                "file" => {
+                    let (_, file) = rest.split_once(' ').ok_or_else(invalid)?;
+                    object.files.push(file.to_string());
+                }
+                "symbol" => {
+                    let fields: Vec<&str> = rest.split(' ').collect();
+                    let [binding, section, symbol] = fields[..] else {
+                        return Err(invalid());
+                    };
+                    object.symbols.push(Symbol {
+                        name: symbol.to_string(),
+                        section: match section {
+                            "text" => Section::Text,
+                            "data" => Section::Data,
+                            _ => return Err(invalid()),
+                        },
+                        binding: match binding {
+                            "local" => Binding::Local,
+                            "global" => Binding::Global,
+                            _ => return Err(invalid()),
+                        },
+                    });
+                }
+                "rom" | "ram" => {
+                    let (origin, length) = rest.split_once(' ').ok_or_else(invalid)?;
+                    let origin: usize = origin.parse().map_err(|_| invalid())?;
+                    let length: usize = length.parse().map_err(|_| invalid())?;
+                    let range = Some(origin..origin + length);
+                    match kind {
+                        "rom" => object.rom = range,
+                        _ => object.ram = range,
+                    }
+                }
+                "extern" => object.externs.push(rest.to_string()),
+                "reloc" => {
+                    let (index, symbol) = rest.split_once(' ').ok_or_else(invalid)?;
+                    object.relocations.push(Relocation {
+                        index: index.parse().map_err(|_| invalid())?,
+                        symbol: symbol.to_string(),
+                    });
+                }
+                "code" => {
+                    let (location, source) = rest.split_once(' ').ok_or_else(invalid)?;
+                    let (file, line) = location.split_once(':').ok_or_else(invalid)?;
+                    let location = Location {
+                        file: file.parse().map_err(|_| invalid())?,
+                        line: line.parse().map_err(|_| invalid())?,
+                    };
+                    let op = Line::from_str(source)
+                        .and_then(Line::to_instruction)
+                        .map_err(|err| error(err.message))?;
+                    object.code.push((location, op));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        program,
+        stack::{self, Direction},
+        types::{BitPos, Reg},
+    };
+
+    // one of each variant, fails to compile when a variant is added until it is listed
+    fn variant(op: &Op) -> &'static str {
+        use Op::*;
+        match op {
+            ADD(..) => "ADD",
+            MUL(..) => "MUL",
+            SUB(..) => "SUB",
+            DIV(..) => "DIV",
+            NOT(..) => "NOT",
+            AND(..) => "AND",
+            OR(..) => "OR",
+            XOR(..) => "XOR",
+            INC(..) => "INC",
+            CMP(..) => "CMP",
+            RR(..) => "RR",
+            RL(..) => "RL",
+            SETB(..) => "SETB",
+            CLRB(..) => "CLRB",
+            CPLB(..) => "CPLB",
+            SETF(..) => "SETF",
+            CLRF(..) => "CLRF",
+            CPLF(..) => "CPLF",
+            LOADBR(..) => "LOADBR",
+            JF(..) => "JF",
+            LOAD(..) => "LOAD",
+            STORE(..) => "STORE",
+            LBL(..) => "LBL",
+            LBH(..) => "LBH",
+            MOV(..) => "MOV",
+            MOVOUT(..) => "MOVOUT",
+            MOVIN(..) => "MOVIN",
+            MOVB(..) => "MOVB",
+            HALT => "HALT",
+            Label(..) => "Label",
+            LoadByte(..) => "LoadByte",
+            Jmp(..) => "Jmp",
+            Branch(..) => "Branch",
+            Call(..) => "Call",
+            Ret => "Ret",
+            Push(..) => "Push",
+            Pop(..) => "Pop",
+            Stack(..) => "Stack",
+            Nop => "Nop",
+            Clr(..) => "Clr",
+            Neg(..) => "Neg",
+            Dec(..) => "Dec",
+            Shl(..) => "Shl",
+            Shr(..) => "Shr",
+            Tst(..) => "Tst",
+            LoadLabel(..) => "LoadLabel",
+            Section(..) => "Section",
+            Words(..) => "Words",
+            Org(..) => "Org",
+            Align(..) => "Align",
+            Global(..) => "Global",
+            Extern(..) => "Extern",
+        }
+    }
+
+    fn every_op() -> Vec<Op> {
+        use Op::*;
+        let (r1, r2, r7) = (Reg(1), Reg(2), Reg(7));
+        let name = |name: &str| name.to_string();
+        vec![
+            ADD(r1, r2, r7),
+            MUL(r1, r2),
+            SUB(r7, r1, r2),
+            DIV(r2, r1),
+            NOT(r1, r2),
+            AND(r1, r2, r7),
+            OR(r1, r2, r7),
+            XOR(r1, r2, r7),
+            INC(r1, r2),
+            CMP(r1, r2),
+            RR(r1, r2),
+            RL(r1, r2),
+            SETB(r1, BitPos(15)),
+            CLRB(r2, BitPos(0)),
+            CPLB(r7, BitPos(7)),
+            SETF(BitPos(0)),
+            CLRF(BitPos(3)),
+            CPLF(BitPos(5)),
+            LOADBR(name("main")),
+            LOADBR(name("main.loop")),
+            LOADBR(name("1$2")),
+            LOADBR(name("skip@3")),
+            JF(BitPos(1)),
+            LOAD(r1, r2),
+            STORE(r1, r2),
+            LBL(r1, 255),
+            LBH(r7, 0),
+            MOV(r1, r2),
+            MOVOUT(r1),
+            MOVIN(r2),
+            MOVB(BitPos(4)),
+            HALT,
+            Label(name("main")),
+            Label(name("main.loop")),
+            Label(name("1$2")),
+            Label(name("skip@3")),
+            Label(name("Mixed_Case")),
+            LoadByte(r1, 0x12, 0x34),
+            Jmp(name("main.loop")),
+            Branch(BitPos(0), false, name("1$0")),
+            Branch(BitPos(2), true, name("skip@1")),
+            Call(name("print")),
+            Ret,
+            Push(r1),
+            Pop(r7),
+            Stack(
+                stack::Stack {
+                    sp: r7,
+                    scratch: r1,
+                    direction: Direction::Up,
+                },
+                200,
+            ),
+            Stack(
+                stack::Stack {
+                    sp: r7,
+                    scratch: Reg(0),
+                    direction: Direction::Down,
+                },
+                0xffff,
+            ),
+            Nop,
+            Clr(r1),
+            Neg(r2),
+            Dec(r7),
+            Shl(r1),
+            Shr(r2),
+            Tst(r7),
+            LoadLabel(r1, name("buffer")),
+            LoadLabel(r2, name("table.end")),
+            Section(program::Section::Data),
+            Section(program::Section::Text),
+            Words(vec![0, 1, 0xffff]),
+            Words(vec![]),
+            Org(0x100),
+            Align(4),
+            Global(vec![name("main"), name("print")]),
+            Extern(vec![name("print")]),
+        ]
+    }
+
+    #[test]
+    fn every_op_round_trips() {
+        let ops = every_op();
+        let mut listed: Vec<&str> = ops.iter().map(variant).collect();
+        listed.dedup();
+        assert_eq!(listed.len(), 52, "every variant is listed once");
+
+        let object = Object {
+            files: vec!["main.asm".to_string()],
+            code: ops
+                .iter()
+                .enumerate()
+                .map(|(i, op)| {
+                    (
+                        Location {
+                            file: 0,
+                            line: i + 1,
+                        },
+                        op.clone(),
+                    )
+                })
+                .collect(),
+            symbols: Vec::new(),
+            externs: Vec::new(),
+            relocations: Vec::new(),
+            rom: None,
+            ram: None,
+        };
+        let read = Object::from_text("main.o", &object.to_text()).unwrap();
+        assert_eq!(read.code.len(), ops.len());
+        for ((location, op), expected) in read.code.iter().zip(&ops) {
+            assert_eq!(
+                format!("{op:?}"),
+                format!("{expected:?}"),
+                "line {}: {}",
+                location.line,
+                expected.to_source()
+            );
+        }
+    }
+
+    #[test]
+    fn symbols_round_trip() {
+        let object = Object {
+            files: vec!["main.asm".to_string(), "lib/inc.asm".to_string()],
+            code: vec![(Location { file: 1, line: 4 }, Op::Call("print".to_string()))],
+            symbols: vec![Symbol {
+                name: "main.loop".to_string(),
+                section: Section::Data,
+                binding: Binding::Global,
+            }],
+            externs: vec!["print".to_string()],
+            relocations: vec![Relocation {
+                index: 0,
+                symbol: "print".to_string(),
+            }],
+            rom: Some(0x10..0x410),
+            ram: Some(0..512),
+        };
+        let text = object.to_text();
+        assert_eq!(Object::from_text("main.o", &text).unwrap().to_text(), text);
+    }
+
+    #[test]
+    fn malformed_objects_are_errors() {
+        let error = |text: &str| match Object::from_text("main.o", text) {
+            Ok(_) => panic!("expected an error for {text:?}"),
+            Err(err) => err.message,
+        };
+        assert_eq!(error("hello\n"), "ERR: main.o is not an object file");
+        for line in [
+            "symbol local text",
+            "reloc x print",
+            "code 0:1 FOO R1",
+            "rom 16",
+        ] {
+            let message = error(&format!("{HEADER}\n{line}\n"));
+            assert!(message.starts_with("ERR: main.o: line 2: "), "{message}");
+        }
+    }
+}
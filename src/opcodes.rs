@@ -0,0 +1,193 @@
+// The opcode values of the processor, read from its Verilog definitions so the
+// encoded words match what it decodes. Both forms are understood, with any of the
+// usual number formats:
+//
+//   `define ADD 5'd0
+//   localparam [4:0] MUL = 5'b00001, SUB = 5'h2;
+//
+// Definitions of other names are ignored, and every machine opcode must be defined.
+use std::collections::HashMap;
+
+use crate::Diagnostic;
+
+// opcodes of the machine instructions, by the names the program task gives them
+pub const MACHINE_OPCODES: [&str; 29] = [
+    "ADD", "MUL", "SUB", "DIV", "NOT", "AND", "OR", "XOR", "INC", "CMP", "RR", "RL", "SETB",
+    "CLRB", "CPLB", "SETF", "CLRF", "CPLF", "LOADBR", "JF", "LOAD", "STORE", "LBL", "LBH", "MOV",
+    "MOVOUT", "MOVIN", "MOVB", "HALT",
+];
+
+// bits of the opcode field, the top of the instruction word
+pub const OPCODE_BITS: u32 = 5;
+
+pub struct Opcodes(HashMap<String, u16>);
+
+impl Opcodes {
+    // the opcodes defined in the Verilog source, name is the file it came from for errors
+    pub fn from_verilog(name: &str, text: &str) -> Result<Self, Diagnostic> {
+        let error = |message: String| Diagnostic::new(format!("ERR: {name}: {message}"));
+        let mut values = HashMap::new();
+        for (opcode, value) in definitions(&strip_comments(text)) {
+            if !MACHINE_OPCODES.contains(&opcode.as_str()) {
+                continue;
+            }
+            let value = number(&value)
+                .ok_or_else(|| error(format!("{opcode} is not defined as a number: {value}")))?;
+            if value >= 1 << OPCODE_BITS {
+                return Err(error(format!(
+                    "{opcode} is {value}, which does not fit in the {OPCODE_BITS} bit opcode"
+                )));
+            }
+            values.insert(opcode, value as u16);
+        }
+        for opcode in MACHINE_OPCODES {
+            let Some(value) = values.get(opcode) else {
+                return Err(error(format!("no value for the {opcode} opcode")));
+            };
+            let same = MACHINE_OPCODES
+                .iter()
+                .find(|other| **other != opcode && values.get(**other) == Some(value));
+            if let Some(other) = same {
+                return Err(error(format!("{opcode} and {other} are both {value}")));
+            }
+        }
+        Ok(Opcodes(values))
+    }
+
+    pub fn get(&self, opcode: &str) -> u16 {
+        self.0[opcode]
+    }
+}
+
+fn strip_comments(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("/*") {
+        result += &rest[..start];
+        rest = rest[start..]
+            .find("*/")
+            .map_or("", |end| &rest[start + end + 2..]);
+    }
+    result += rest;
+    result
+        .lines()
+        .map(|line| line.split("//").next().unwrap())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// (name, value) of every `define, parameter and localparam
+fn definitions(text: &str) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() == Some("`define") {
+            if let (Some(name), Some(value)) = (words.next(), words.next()) {
+                result.push((name.to_string(), value.to_string()));
+            }
+        }
+    }
+    // parameters can also be in a module header, ended by `)` rather than `;`
+    for statement in text.split([';', ')']) {
+        let Some(start) = ["localparam", "parameter"]
+            .iter()
+            .filter_map(|keyword| word_position(statement, keyword).map(|i| i + keyword.len()))
+            .min()
+        else {
+            continue;
+        };
+        for assignment in statement[start..].split(',') {
+            let Some((name, value)) = assignment.split_once('=') else {
+                continue;
+            };
+            // the last word, after any range or type
+            let name = name
+                .trim_end()
+                .rsplit([' ', '\t', '\n', ']'])
+                .next()
+                .unwrap_or("");
+            result.push((name.to_string(), value.trim().to_string()));
+        }
+    }
+    result
+}
+
+// where a keyword starts as a word of its own
+fn word_position(text: &str, keyword: &str) -> Option<usize> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    text.match_indices(keyword)
+        .map(|(i, _)| i)
+        .find(|&i| !text[..i].ends_with(is_word) && !text[i + keyword.len()..].starts_with(is_word))
+}
+
+// a Verilog number: 12, 5'd12, 5'b01100, 'hC, with any _ separators
+fn number(text: &str) -> Option<u64> {
+    let text = text.replace('_', "");
+    let Some((_, based)) = text.split_once('\'') else {
+        return text.parse().ok();
+    };
+    let based = based.trim_start_matches(['s', 'S']);
+    let radix = match based.chars().next()?.to_ascii_lowercase() {
+        'b' => 2,
+        'o' => 8,
+        'd' => 10,
+        'h' => 16,
+        _ => return None,
+    };
+    u64::from_str_radix(based[1..].trim(), radix).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verilog(lines: &[String]) -> String {
+        lines.join("\n")
+    }
+
+    #[test]
+    fn reads_every_form() {
+        let mut lines: Vec<String> = MACHINE_OPCODES[4..]
+            .iter()
+            .zip(4..)
+            .map(|(opcode, value)| format!("`define {opcode} 5'd{value} // {opcode}"))
+            .collect();
+        lines.push("/* ADD = 5'd9; */ localparam [4:0] ADD = 5'b0_0000, MUL = 'h1;".into());
+        lines.push("module core #(parameter SUB = 2, DIV = 5'o3) ();".into());
+        let opcodes = Opcodes::from_verilog("core.v", &verilog(&lines)).unwrap();
+        let values: Vec<u16> = MACHINE_OPCODES.iter().map(|op| opcodes.get(op)).collect();
+        assert_eq!(values, (0..29).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn errors() {
+        let defines = |value: &str| {
+            let mut lines: Vec<String> = MACHINE_OPCODES[1..]
+                .iter()
+                .zip(1..)
+                .map(|(opcode, value)| format!("`define {opcode} {value}"))
+                .collect();
+            lines.push(value.to_string());
+            Opcodes::from_verilog("core.v", &verilog(&lines))
+                .err()
+                .map(|err| err.message)
+        };
+        assert_eq!(defines("`define ADD 0"), None);
+        assert_eq!(
+            defines(""),
+            Some("ERR: core.v: no value for the ADD opcode".into())
+        );
+        assert_eq!(
+            defines("`define ADD 5'd32"),
+            Some("ERR: core.v: ADD is 32, which does not fit in the 5 bit opcode".into())
+        );
+        assert_eq!(
+            defines("`define ADD 5'bx"),
+            Some("ERR: core.v: ADD is not defined as a number: 5'bx".into())
+        );
+        assert_eq!(
+            defines("`define ADD 28"),
+            Some("ERR: core.v: ADD and HALT are both 28".into())
+        );
+    }
+}
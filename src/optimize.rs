@@ -0,0 +1,188 @@
+// Shortest sequences for `MOV Rn, #imm`, which otherwise always takes LBH and LBL:
+//
+// - #0 becomes `XOR Rn, Rn, Rn`. Unlike LBH and LBL this updates the Z, N and P flags.
+// - LBL and LBH keep the other byte of the register, so when an earlier immediate load
+//   in the same straight line code (no label in between) left the register holding a
+//   known value with no other write since, only the bytes that differ are loaded. If
+//   neither differs, no instruction is emitted.
+// - Otherwise both LBH and LBL are needed, as nothing guarantees the high byte is clear.
+use std::collections::HashMap;
+
+use crate::{instruction::Op, preprocess::Location, types::Reg};
+
+pub fn immediates(instructions: Vec<(Location, Op)>) -> Vec<(Location, Op)> {
+    let mut known: HashMap<u8, u16> = HashMap::new();
+    let mut result = Vec::new();
+    for (line, op) in instructions {
+        let ops = match op {
+            Op::LoadByte(reg, upper, lower) => {
+                let value = u16::from_be_bytes([upper, lower]);
+                let ops = load(reg, value, known.get(&reg.0).copied());
+                known.insert(reg.0, value);
+                ops
+            }
+            Op::Label(_) | Op::Org(_) | Op::Align(_) => {
+                // a jump could land here with any register values
+                known.clear();
+                vec![op]
+            }
+            op => {
+                if let Some(reg) = op.writes() {
+                    known.remove(&reg.0);
+                }
+                vec![op]
+            }
+        };
+        result.extend(ops.into_iter().map(|op| (line, op)));
+    }
+    result
+}
+
+fn load(reg: Reg, value: u16, current: Option<u16>) -> Vec<Op> {
+    let [upper, lower] = value.to_be_bytes();
+    match current {
+        Some(current) => {
+            let [current_upper, current_lower] = current.to_be_bytes();
+            let mut ops = Vec::new();
+            if upper != current_upper {
+                ops.push(Op::LBH(reg, upper));
+            }
+            if lower != current_lower {
+                ops.push(Op::LBL(reg, lower));
+            }
+            ops
+        }
+        None if value == 0 => vec![Op::XOR(reg, reg, reg)],
+        None => vec![Op::LoadByte(reg, upper, lower)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        preprocess::Sources,
+        stack::{self, Direction, Stack},
+    };
+
+    const R1: Reg = Reg(1);
+
+    // the instructions optimized, as text since Op has no PartialEq
+    fn optimized(ops: Vec<Op>) -> String {
+        let at = Location { file: 0, line: 1 };
+        let ops = immediates(ops.into_iter().map(|op| (at, op)).collect());
+        format!(
+            "{:?}",
+            ops.into_iter().map(|(_, op)| op).collect::<Vec<_>>()
+        )
+    }
+
+    fn ops(ops: Vec<Op>) -> String {
+        format!("{ops:?}")
+    }
+
+    #[test]
+    fn zero_is_xor() {
+        assert_eq!(
+            optimized(vec![Op::LoadByte(R1, 0, 0)]),
+            ops(vec![Op::XOR(R1, R1, R1)])
+        );
+    }
+
+    #[test]
+    fn only_changed_bytes_are_loaded() {
+        assert_eq!(
+            optimized(vec![
+                Op::LoadByte(R1, 0x12, 0x34),
+                Op::LoadByte(R1, 0x12, 0x56),
+                Op::LoadByte(R1, 0x78, 0x56),
+                Op::LoadByte(R1, 0x78, 0x56),
+            ]),
+            ops(vec![
+                Op::LoadByte(R1, 0x12, 0x34),
+                Op::LBL(R1, 0x56),
+                Op::LBH(R1, 0x78),
+            ])
+        );
+    }
+
+    #[test]
+    fn labels_forget_values() {
+        assert_eq!(
+            optimized(vec![
+                Op::LoadByte(R1, 0, 5),
+                Op::Label("again".to_string()),
+                Op::LoadByte(R1, 0, 5),
+            ]),
+            ops(vec![
+                Op::LoadByte(R1, 0, 5),
+                Op::Label("again".to_string()),
+                Op::LoadByte(R1, 0, 5),
+            ])
+        );
+    }
+
+    #[test]
+    fn calls_forget_values() {
+        let at = Location { file: 0, line: 1 };
+        let stack = Stack {
+            sp: Reg(7),
+            scratch: Reg(0),
+            direction: Direction::Up,
+        };
+        let code = [
+            Op::Stack(stack, 200),
+            Op::LoadByte(R1, 0, 5),
+            Op::Call("sub".to_string()),
+            Op::LoadByte(R1, 0, 5),
+        ]
+        .into_iter()
+        .map(|op| (at, op))
+        .collect();
+        let code = stack::expand(code, &Sources::default()).unwrap();
+        let code = immediates(code);
+        // the subroutine could have changed R1, so it is loaded again after the return
+        let (_, last) = code.last().unwrap();
+        assert_eq!(format!("{last:?}"), format!("{:?}", Op::LoadByte(R1, 0, 5)));
+    }
+
+    #[test]
+    fn writes_forget_values() {
+        let writes = [
+            Op::LOAD(R1, Reg(2)),
+            Op::MOVIN(R1),
+            Op::ADD(R1, Reg(2), Reg(3)),
+            Op::INC(R1, R1),
+            Op::Pop(R1),
+        ];
+        for write in writes {
+            let code = vec![
+                Op::LoadByte(R1, 0, 5),
+                write.clone(),
+                Op::LoadByte(R1, 0, 5),
+            ];
+            assert_eq!(optimized(code.clone()), ops(code), "after {write:?}");
+        }
+        // MUL and DIV write R0
+        let code = vec![
+            Op::LoadByte(Reg(0), 0, 5),
+            Op::MUL(R1, R1),
+            Op::LoadByte(Reg(0), 0, 5),
+        ];
+        assert_eq!(optimized(code.clone()), ops(code));
+        // nothing is written, so the value is still there
+        assert_eq!(
+            optimized(vec![
+                Op::LoadByte(R1, 0, 5),
+                Op::STORE(Reg(2), R1),
+                Op::CMP(R1, Reg(2)),
+                Op::LoadByte(R1, 0, 5),
+            ]),
+            ops(vec![
+                Op::LoadByte(R1, 0, 5),
+                Op::STORE(Reg(2), R1),
+                Op::CMP(R1, Reg(2)),
+            ])
+        );
+    }
+}
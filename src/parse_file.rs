@@ -1,19 +1,47 @@
 use crate::{
+    expr,
     instruction::Op,
+    program::Section,
+    stack::{Direction, Stack},
     types::{BitPos, Reg},
+    Diagnostic,
 };
 
 pub enum Line {
-    Move { source: Data, destination: Data },
+    Move {
+        source: Data,
+        destination: Data,
+    },
     MathOneArg(OneArgOp, Reg, Reg),
     MathTwoArg(TwoArgOp, Reg, Reg, Reg),
     MathNoOutTwoArg(NoOutTwoArgOp, Reg, Reg), // DIV, MUL, CMP
     BitOp(BitOp, Bit),
     Movb(BitPos),
+    // LBH when high, LBL otherwise
+    LoadHalf(bool, Reg, u8),
     JumpIf(BitPos), // Flag Bit Pos
     LoadBranch(String),
+    Jump(String),
+    Branch {
+        flag: BitPos,
+        negate: bool,
+        label: String,
+    },
     Label(String),
+    Call(String),
+    Return,
+    Push(Reg),
+    Pop(Reg),
+    Stack(Stack, u16),
+    Synthetic(SyntheticOp, Reg),
+    Nop,
     Halt,
+    Section(Section),
+    Words(Vec<u16>),
+    Org(u16),
+    Align(u16),
+    Global(Vec<String>),
+    Extern(Vec<String>),
 }
 
 pub enum OneArgOp {
@@ -37,6 +65,15 @@ pub enum NoOutTwoArgOp {
     Cmp,
 }
 
+pub enum SyntheticOp {
+    Clear,
+    Negate,
+    Decrement,
+    ShiftLeft,
+    ShiftRight,
+    Test,
+}
+
 pub enum BitOp {
     Set,
     Clear,
@@ -50,59 +87,178 @@ pub enum Bit {
 
 pub enum Data {
     Immediate(u16),
+    // the address of a data label
+    Symbol(String),
     Direct(Reg),
     Indirect(Reg),
     In,
     Out,
 }
 
+// highest bit of a register or of the IN port
+const MAX_BIT: u8 = 15;
+
+fn is_reg(arg: &str) -> bool {
+    matches!(
+        arg.to_lowercase().as_ref(),
+        "r0" | "r1" | "r2" | "r3" | "r4" | "r5" | "r6" | "r7"
+    )
+}
+
+// a 16 bit number, in any of the formats of expr::parse_number
+pub fn parse_number(arg: &str) -> Option<u16> {
+    expr::parse_number(&arg.to_lowercase()).and_then(|number| u16::try_from(number).ok())
+}
+
+fn parse_immediate(arg: &str) -> Result<Data, Diagnostic> {
+    Ok(match parse_number(arg) {
+        Some(value) => Data::Immediate(value),
+        None if arg.starts_with(|c: char| c.is_alphabetic() || c == '_') => {
+            Data::Symbol(arg.into())
+        }
+        None => return Err(Diagnostic::new("immediate data cannot be parsed")),
+    })
+}
+
+// the characters of a quoted string with C escapes, one per word, followed by a 0 word
+fn parse_string(arg: &str) -> Result<Vec<u16>, Diagnostic> {
+    let string = arg
+        .trim()
+        .strip_prefix('"')
+        .and_then(|arg| arg.strip_suffix('"'))
+        .ok_or_else(|| Diagnostic::new(".string expects a quoted string"))?;
+    let mut words = Vec::new();
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"')) => c,
+                c => {
+                    return Err(Diagnostic::new(format!(
+                        "invalid escape in string: \\{}",
+                        c.map_or(String::new(), String::from)
+                    )))
+                }
+            },
+            c => c,
+        };
+        let word = u16::try_from(c as u32)
+            .map_err(|_| Diagnostic::new("character does not fit in a 16 bit word"))?;
+        words.push(word);
+    }
+    words.push(0);
+    Ok(words)
+}
+
+// conditional branch mnemonics, with the flag each tests and whether the jump is
+// taken when the flag is clear instead of set
+const BRANCHES: [(&str, &str, bool); 16] = [
+    ("jc", "c", false),
+    ("jnc", "c", true),
+    ("jv", "v", false),
+    ("jnv", "v", true),
+    ("jgt", "cmp", false),
+    ("jle", "cmp", true),
+    ("jeq", "eq", false),
+    ("jne", "eq", true),
+    ("jio", "io", false),
+    ("jnio", "io", true),
+    ("jp", "p", false),
+    ("jnp", "p", true),
+    ("jn", "n", false),
+    ("jnn", "n", true),
+    ("jz", "z", false),
+    ("jnz", "z", true),
+];
+
+fn branch_condition(op: &str) -> Option<(&'static str, bool)> {
+    BRANCHES
+        .iter()
+        .find(|(name, _, _)| *name == op)
+        .map(|(_, flag, negate)| (*flag, *negate))
+}
+
+// the mnemonic branching on the flag, inverse of branch_condition
+pub fn branch_mnemonic(flag: BitPos, negate: bool) -> &'static str {
+    BRANCHES
+        .iter()
+        .find(|(_, name, n)| BitPos::from_flag(name).ok() == Some(flag) && *n == negate)
+        .map(|(name, _, _)| *name)
+        .unwrap()
+}
+
 impl Line {
-    pub fn from_str(line: &str) -> Self {
-        let line = line.trim().to_lowercase();
-        let (op, args): (String, Vec<&str>) = {
-            match line.split_once(' ') {
-                Some((first, rest)) => (
-                    first.into(),
-                    rest.split(',').map(|arg| arg.trim()).collect(),
-                ),
-                None => (line.clone(), vec![]),
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(line: &str) -> Result<Self, Diagnostic> {
+        // parsed before lowercasing, to keep the case of the string
+        if let Some((op, string)) = line.trim().split_once(char::is_whitespace) {
+            if op.eq_ignore_ascii_case(".string") {
+                return Ok(Self::Words(parse_string(string)?));
             }
+        }
+        let original = line.trim();
+        // the mnemonic is matched in lowercase, the arguments are kept as written so
+        // names keep their case and errors show what was in the source
+        let (op, args): (String, Vec<&str>) = match original.split_once(char::is_whitespace) {
+            Some((first, rest)) => (
+                first.to_lowercase(),
+                rest.split(',').map(|arg| arg.trim()).collect(),
+            ),
+            None => (original.to_lowercase(), vec![]),
         };
 
         if args.is_empty() {
             if op == "halt" {
-                return Self::Halt;
+                return Ok(Self::Halt);
             }
-            if let Some((op, _)) = op.split_once(':') {
-                return Self::Label(op.into());
+            if op == "ret" {
+                return Ok(Self::Return);
+            }
+            if op == "nop" {
+                return Ok(Self::Nop);
+            }
+            if op == ".text" {
+                return Ok(Self::Section(Section::Text));
+            }
+            if op == ".data" {
+                return Ok(Self::Section(Section::Data));
+            }
+            if let Some((label, _)) = original.split_once(':') {
+                return Ok(Self::Label(label.into()));
             } else {
-                panic!("ERR: [{line:?}], args is empty but also is not HALT or a label")
+                return Err(Diagnostic::new(
+                    "no arguments given, and not HALT, RET, NOP, .text, .data or a label",
+                ));
             }
         }
 
-        match op.as_ref() {
+        Ok(match op.as_ref() {
             "mov" => {
                 if args.len() != 2 {
-                    panic!(
+                    return Err(Diagnostic::new(format!(
                         "instruction expected 2 arguments but received {} arguments: {}",
                         args.len(),
                         op.to_uppercase()
-                    );
+                    )));
                 }
                 let destination = {
                     let arg = args[0];
                     if arg.starts_with('@') {
                         let (_, arg) = arg.split_once('@').unwrap();
-                        Data::Indirect(Reg::from_str(arg))
+                        Data::Indirect(Reg::from_str(arg)?)
                     } else if arg.starts_with('#') {
                         let (_, arg) = arg.split_once('#').unwrap();
-                        Data::Immediate(arg.parse().expect("immediate data cannot be parsed"))
-                    } else if arg == "in" {
+                        parse_immediate(arg)?
+                    } else if arg.eq_ignore_ascii_case("in") {
                         Data::In
-                    } else if arg == "out" {
+                    } else if arg.eq_ignore_ascii_case("out") {
                         Data::Out
                     } else {
-                        Data::Direct(Reg::from_str(arg))
+                        Data::Direct(Reg::from_str(arg)?)
                     }
                 };
 
@@ -110,16 +266,16 @@ impl Line {
                     let arg = args[1];
                     if arg.starts_with('@') {
                         let (_, arg) = arg.split_once('@').unwrap();
-                        Data::Indirect(Reg::from_str(arg))
+                        Data::Indirect(Reg::from_str(arg)?)
                     } else if arg.starts_with('#') {
                         let (_, arg) = arg.split_once('#').unwrap();
-                        Data::Immediate(arg.parse().expect("immediate data cannot be parsed"))
-                    } else if arg == "in" {
+                        parse_immediate(arg)?
+                    } else if arg.eq_ignore_ascii_case("in") {
                         Data::In
-                    } else if arg == "out" {
+                    } else if arg.eq_ignore_ascii_case("out") {
                         Data::Out
                     } else {
-                        Data::Direct(Reg::from_str(arg))
+                        Data::Direct(Reg::from_str(arg)?)
                     }
                 };
 
@@ -129,17 +285,17 @@ impl Line {
                 }
             }
             "not" | "inc" | "rl" | "rr" => {
-                if op == "inc" {
+                if op == "inc" && args.len() == 1 {
                     let op = OneArgOp::Inc;
-                    let reg = Reg::from_str(args[0]);
-                    return Self::MathOneArg(op, reg, reg);
+                    let reg = Reg::from_str(args[0])?;
+                    return Ok(Self::MathOneArg(op, reg, reg));
                 }
                 if args.len() != 2 {
-                    panic!(
+                    return Err(Diagnostic::new(format!(
                         "instruction expected 2 arguments but received {} arguments: {}",
                         args.len(),
                         op.to_uppercase()
-                    );
+                    )));
                 }
                 let op = match op.as_ref() {
                     "not" => OneArgOp::Not,
@@ -148,15 +304,15 @@ impl Line {
                     "rr" => OneArgOp::Rr,
                     _ => unreachable!(),
                 };
-                Self::MathOneArg(op, Reg::from_str(args[0]), Reg::from_str(args[1]))
+                Self::MathOneArg(op, Reg::from_str(args[0])?, Reg::from_str(args[1])?)
             }
             "add" | "sub" | "and" | "or" | "xor" => {
                 if args.len() != 3 {
-                    panic!(
+                    return Err(Diagnostic::new(format!(
                         "instruction expected 3 arguments but received {} arguments: {}",
                         args.len(),
                         op.to_uppercase()
-                    );
+                    )));
                 }
                 let op = match op.as_ref() {
                     "add" => TwoArgOp::Add,
@@ -168,18 +324,18 @@ impl Line {
                 };
                 Self::MathTwoArg(
                     op,
-                    Reg::from_str(args[0]),
-                    Reg::from_str(args[1]),
-                    Reg::from_str(args[2]),
+                    Reg::from_str(args[0])?,
+                    Reg::from_str(args[1])?,
+                    Reg::from_str(args[2])?,
                 )
             }
             "mul" | "div" | "cmp" => {
                 if args.len() != 2 {
-                    panic!(
+                    return Err(Diagnostic::new(format!(
                         "instruction expected 2 arguments but received {} arguments: {}",
                         args.len(),
                         op.to_uppercase()
-                    );
+                    )));
                 }
                 let op = match op.as_ref() {
                     "mul" => NoOutTwoArgOp::Mul,
@@ -187,15 +343,36 @@ impl Line {
                     "cmp" => NoOutTwoArgOp::Cmp,
                     _ => unreachable!(),
                 };
-                Self::MathNoOutTwoArg(op, Reg::from_str(args[0]), Reg::from_str(args[1]))
+                Self::MathNoOutTwoArg(op, Reg::from_str(args[0])?, Reg::from_str(args[1])?)
+            }
+            "clr" if args.len() == 1 && is_reg(args[0]) => {
+                Self::Synthetic(SyntheticOp::Clear, Reg::from_str(args[0])?)
+            }
+            "neg" | "dec" | "shl" | "shr" | "tst" => {
+                if args.len() != 1 {
+                    return Err(Diagnostic::new(format!(
+                        "instruction expected 1 argument but received {} arguments: {}",
+                        args.len(),
+                        op.to_uppercase()
+                    )));
+                }
+                let op = match op.as_ref() {
+                    "neg" => SyntheticOp::Negate,
+                    "dec" => SyntheticOp::Decrement,
+                    "shl" => SyntheticOp::ShiftLeft,
+                    "shr" => SyntheticOp::ShiftRight,
+                    "tst" => SyntheticOp::Test,
+                    _ => unreachable!(),
+                };
+                Self::Synthetic(op, Reg::from_str(args[0])?)
             }
             "set" | "clr" | "cpl" => {
                 if args.len() != 1 {
-                    panic!(
+                    return Err(Diagnostic::new(format!(
                         "instruction expected 1 argument but received {} arguments: {}",
                         args.len(),
                         op.to_uppercase()
-                    );
+                    )));
                 }
                 let bit_op = match op.as_ref() {
                     "set" => BitOp::Set,
@@ -207,58 +384,216 @@ impl Line {
                     Some((reg, bit_pos)) => Self::BitOp(
                         bit_op,
                         Bit::Reg(
-                            Reg::from_str(reg),
-                            BitPos(bit_pos.parse().expect("invalid bit index")),
+                            Reg::from_str(reg)?,
+                            BitPos(
+                                bit_pos
+                                    .parse()
+                                    .ok()
+                                    .filter(|pos| *pos <= MAX_BIT)
+                                    .ok_or_else(|| {
+                                        Diagnostic::new("invalid bit index, expected 0 to 15")
+                                    })?,
+                            ),
                         ),
                     ),
-                    None => Self::BitOp(bit_op, Bit::Flag(BitPos::from_flag(args[0]))),
+                    None => Self::BitOp(bit_op, Bit::Flag(BitPos::from_flag(args[0])?)),
                 }
             }
             "movb" => {
                 if args.len() != 1 {
-                    panic!(
+                    return Err(Diagnostic::new(format!(
                         "instruction expected 1 argument but received {} arguments: {}",
                         args.len(),
                         op.to_uppercase()
-                    );
+                    )));
                 }
 
                 let index = args[0]
-                    .split_once("IN.")
-                    .expect("must specify movb from IN reg")
+                    .to_lowercase()
+                    .split_once("in.")
+                    .ok_or_else(|| Diagnostic::new("must specify movb from IN reg"))?
                     .1
                     .parse()
-                    .expect("cannot parse bit index");
+                    .ok()
+                    .filter(|pos| *pos <= MAX_BIT)
+                    .ok_or_else(|| Diagnostic::new("invalid bit index, expected IN.0 to IN.15"))?;
                 Self::Movb(BitPos(index))
             }
+            "lbl" | "lbh" => {
+                if args.len() != 2 {
+                    return Err(Diagnostic::new(format!(
+                        "instruction expected 2 arguments but received {} arguments: {}",
+                        args.len(),
+                        op.to_uppercase()
+                    )));
+                }
+                let byte = args[1]
+                    .strip_prefix('#')
+                    .and_then(parse_number)
+                    .filter(|byte| *byte <= 0xFF)
+                    .ok_or_else(|| Diagnostic::new("invalid byte, expected #0 to #255"))?;
+                Self::LoadHalf(op == "lbh", Reg::from_str(args[0])?, byte as u8)
+            }
             "jf" => {
                 if args.len() != 1 {
-                    panic!(
+                    return Err(Diagnostic::new(format!(
                         "instruction expected 1 argument but received {} arguments: {}",
                         args.len(),
                         op.to_uppercase()
-                    );
+                    )));
                 }
-                Self::JumpIf(BitPos::from_flag(&args[0]))
+                Self::JumpIf(BitPos::from_flag(args[0])?)
             }
             "loadbr" => {
                 if args.len() != 1 {
-                    panic!(
+                    return Err(Diagnostic::new(format!(
                         "instruction expected 1 argument but received {} arguments: {}",
                         args.len(),
                         op.to_uppercase()
-                    );
+                    )));
                 }
                 Self::LoadBranch(args[0].into())
             }
+            "jmp" => {
+                if args.len() != 1 {
+                    return Err(Diagnostic::new(format!(
+                        "instruction expected 1 argument but received {} arguments: {}",
+                        args.len(),
+                        op.to_uppercase()
+                    )));
+                }
+                Self::Jump(args[0].into())
+            }
+            "call" => {
+                if args.len() != 1 {
+                    return Err(Diagnostic::new(format!(
+                        "instruction expected 1 argument but received {} arguments: {}",
+                        args.len(),
+                        op.to_uppercase()
+                    )));
+                }
+                Self::Call(args[0].into())
+            }
+            "push" | "pop" => {
+                if args.len() != 1 {
+                    return Err(Diagnostic::new(format!(
+                        "instruction expected 1 argument but received {} arguments: {}",
+                        args.len(),
+                        op.to_uppercase()
+                    )));
+                }
+                let reg = Reg::from_str(args[0])?;
+                match op.as_ref() {
+                    "push" => Self::Push(reg),
+                    "pop" => Self::Pop(reg),
+                    _ => unreachable!(),
+                }
+            }
+            ".stack" => {
+                if args.len() < 2 || args.len() > 4 {
+                    return Err(Diagnostic::new(format!(
+                        "directive expected 2 to 4 arguments but received {} arguments: .STACK",
+                        args.len()
+                    )));
+                }
+                let mut stack = Stack {
+                    sp: Reg::from_str(args[0])?,
+                    scratch: Reg(0),
+                    direction: Direction::Up,
+                };
+                for arg in &args[2..] {
+                    match arg.to_lowercase().as_ref() {
+                        "up" => stack.direction = Direction::Up,
+                        "down" => stack.direction = Direction::Down,
+                        reg => stack.scratch = Reg::from_str(reg)?,
+                    }
+                }
+                let base = parse_number(args[1])
+                    .ok_or_else(|| Diagnostic::new("invalid stack base address"))?;
+                Self::Stack(stack, base)
+            }
+            ".word" => Self::Words(
+                args.iter()
+                    .map(|arg| {
+                        parse_number(arg).ok_or_else(|| Diagnostic::new("invalid .word value"))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            ".byte" => Self::Words(
+                args.iter()
+                    .map(|arg| {
+                        parse_number(arg)
+                            .filter(|byte| *byte <= 0xFF)
+                            .ok_or_else(|| Diagnostic::new("invalid .byte value"))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            ".global" => Self::Global(args.iter().map(|name| name.to_string()).collect()),
+            ".extern" => Self::Extern(args.iter().map(|name| name.to_string()).collect()),
+            ".org" | ".align" => {
+                if args.len() != 1 {
+                    return Err(Diagnostic::new(format!(
+                        "directive expected 1 argument but received {} arguments: {}",
+                        args.len(),
+                        op.to_uppercase()
+                    )));
+                }
+                match op.as_ref() {
+                    ".org" => Self::Org(
+                        parse_number(args[0])
+                            .ok_or_else(|| Diagnostic::new("invalid .org address"))?,
+                    ),
+                    _ => Self::Align(
+                        parse_number(args[0])
+                            .filter(|n| *n > 0)
+                            .ok_or_else(|| Diagnostic::new("invalid .align size"))?,
+                    ),
+                }
+            }
+            ".space" => {
+                if args.len() > 2 {
+                    return Err(Diagnostic::new(format!(
+                        "directive expected 1 or 2 arguments but received {} arguments: .SPACE",
+                        args.len()
+                    )));
+                }
+                let count =
+                    parse_number(args[0]).ok_or_else(|| Diagnostic::new("invalid .space size"))?;
+                let fill = match args.get(1) {
+                    Some(fill) => parse_number(fill)
+                        .ok_or_else(|| Diagnostic::new("invalid .space fill value"))?,
+                    None => 0,
+                };
+                Self::Words(vec![fill; count as usize])
+            }
+            op if branch_condition(op).is_some() => {
+                if args.len() != 1 {
+                    return Err(Diagnostic::new(format!(
+                        "instruction expected 1 argument but received {} arguments: {}",
+                        args.len(),
+                        op.to_uppercase()
+                    )));
+                }
+                let (flag, negate) = branch_condition(op).unwrap();
+                Self::Branch {
+                    flag: BitPos::from_flag(flag)?,
+                    negate,
+                    label: args[0].into(),
+                }
+            }
             _ => {
-                panic!("ERR: [{line}], invalid operator {op}")
+                let op = original
+                    .split(char::is_whitespace)
+                    .next()
+                    .unwrap_or_default();
+                return Err(Diagnostic::new(format!("invalid operator {op}")));
             }
-        }
+        })
     }
-    pub fn to_instruction(self) -> Op {
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_instruction(self) -> Result<Op, Diagnostic> {
         use Op::*;
-        match self {
+        Ok(match self {
             Line::Move {
                 destination,
                 source,
@@ -269,28 +604,43 @@ impl Line {
                 (Data::Direct(reg), Data::Immediate(data)) => {
                     LoadByte(reg, data.to_be_bytes()[0], data.to_be_bytes()[1])
                 }
+                (Data::Direct(reg), Data::Symbol(label)) => LoadLabel(reg, label),
                 (Data::Direct(dest), Data::In) => MOVIN(dest),
 
                 (Data::Indirect(dest), Data::Direct(src)) => STORE(dest, src),
                 (Data::Out, Data::Direct(src)) => MOVOUT(src),
 
-                (Data::Immediate(_), _) => {
-                    panic!("ERR: you cannot have immediate data as destination")
+                (Data::Immediate(_) | Data::Symbol(_), _) => {
+                    return Err(Diagnostic::new(
+                        "you cannot have immediate data as destination",
+                    ))
                 }
-                (Data::Indirect(reg), _) => {
-                    panic!("ERR: must use indirect access with direct access")
+                (Data::Indirect(_), _) => {
+                    return Err(Diagnostic::new(
+                        "must use indirect access with direct access",
+                    ))
                 }
-                (_, Data::Indirect(reg)) => {
-                    panic!("ERR: must use indirect access with direct access")
+                (_, Data::Indirect(_)) => {
+                    return Err(Diagnostic::new(
+                        "must use indirect access with direct access",
+                    ))
+                }
+                (Data::In, _) => {
+                    return Err(Diagnostic::new("Input register can never be destination"))
                 }
-                (Data::In, _) => panic!("ERR: Input register can never be destination"),
                 (_, Data::In) => {
-                    panic!("ERR: Input register data can only be read to general purpose register")
+                    return Err(Diagnostic::new(
+                        "Input register data can only be read to general purpose register",
+                    ))
+                }
+                (_, Data::Out) => {
+                    return Err(Diagnostic::new("Output register can never be source"))
+                }
+                (Data::Out, _) => {
+                    return Err(Diagnostic::new(
+                        "Output register can only be written to from general purpose register",
+                    ))
                 }
-                (_, Data::Out) => panic!("ERR: Output register can never be source"),
-                (Data::Out, _) => panic!(
-                    "ERR: Output register can only be written to from general purpose register"
-                ),
             },
             Line::MathOneArg(op, reg_out, reg_in) => match op {
                 OneArgOp::Not => NOT(reg_out, reg_in),
@@ -327,10 +677,38 @@ impl Line {
                 },
             },
             Line::Movb(bit_pos) => MOVB(bit_pos),
+            Line::LoadHalf(true, reg, byte) => LBH(reg, byte),
+            Line::LoadHalf(false, reg, byte) => LBL(reg, byte),
             Line::JumpIf(bit_pos) => JF(bit_pos),
             Line::Label(name) => Label(name),
+            Line::Call(name) => Call(name),
+            Line::Return => Ret,
+            Line::Push(reg) => Push(reg),
+            Line::Pop(reg) => Pop(reg),
+            Line::Stack(stack, base) => Stack(stack, base),
+            Line::Synthetic(op, reg) => match op {
+                SyntheticOp::Clear => Clr(reg),
+                SyntheticOp::Negate => Neg(reg),
+                SyntheticOp::Decrement => Dec(reg),
+                SyntheticOp::ShiftLeft => Shl(reg),
+                SyntheticOp::ShiftRight => Shr(reg),
+                SyntheticOp::Test => Tst(reg),
+            },
+            Line::Nop => Nop,
             Line::LoadBranch(name) => LOADBR(name),
+            Line::Jump(name) => Jmp(name),
+            Line::Branch {
+                flag,
+                negate,
+                label,
+            } => Branch(flag, negate, label),
             Line::Halt => HALT,
-        }
+            Line::Section(section) => Section(section),
+            Line::Words(words) => Words(words),
+            Line::Org(address) => Org(address),
+            Line::Align(size) => Align(size),
+            Line::Global(names) => Global(names),
+            Line::Extern(names) => Extern(names),
+        })
     }
 }
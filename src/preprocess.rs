@@ -0,0 +1,698 @@
+// Expands macros before lines are parsed:
+//
+//   .macro name arg1, arg2
+//     ADD \arg1, \arg1, \arg2
+//     MOV \arg2, #\@
+//   %%skip:
+//     LOADBR %%skip
+//   .endm
+//
+// `\arg` is replaced by the argument given for arg, `\@` by a number unique to each
+// expansion, and `%%label` by a label unique to each expansion. Macros can invoke
+// other macros, up to MAX_DEPTH expansions deep.
+//
+// `.rept count ... .endr` repeats the lines in between, and `.irp name, a, b ... .endr`
+// repeats them once for each value, with `\name` replaced by the value. Both treat
+// `\@` and `%%label` like macros do, counting each repetition as an expansion.
+//
+// `.equ NAME, expr` defines a constant, which can be used as an immediate (`#NAME`)
+// and in the conditions of
+//
+//   .if expr / .ifdef NAME / .ifndef NAME
+//   .elif expr
+//   .else
+//   .endif
+//
+// where only the lines of the first branch whose condition holds are assembled.
+// Constants can also be given on the command line with -D.
+//
+// `.include "lib/math.asm"` inserts another file, searched for next to the including
+// file and then in each include path. A file containing `.once` is only included the
+// first time.
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{expr, program::Options, sim::DATA_MEM_SIZE, Diagnostic};
+
+const MAX_DEPTH: usize = 64;
+// more repetitions than words in the largest memory could never all be placed
+const MAX_REPT: i64 = DATA_MEM_SIZE as i64;
+
+// a line in one of the source files, files are numbered in the order they were read
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    pub file: usize,
+    // 1 based
+    pub line: usize,
+}
+
+pub struct SourceFile {
+    // the path as given on the command line or found for .include
+    pub name: String,
+    pub lines: Vec<String>,
+    pub included_from: Option<Location>,
+    path: PathBuf,
+}
+
+// every file read while assembling, the first is the one given on the command line
+#[derive(Default)]
+pub struct Sources {
+    pub files: Vec<SourceFile>,
+}
+
+impl Sources {
+    // the files named in an object, empty when they cannot be read
+    pub fn from_names(names: &[String]) -> Self {
+        let files = names
+            .iter()
+            .map(|name| SourceFile {
+                name: name.clone(),
+                lines: fs::read_to_string(name)
+                    .map(|text| text.lines().map(String::from).collect())
+                    .unwrap_or_default(),
+                included_from: None,
+                path: PathBuf::from(name),
+            })
+            .collect();
+        Sources { files }
+    }
+
+    pub fn text(&self, location: Location) -> &str {
+        self.files[location.file]
+            .lines
+            .get(location.line - 1)
+            .map_or("", String::as_str)
+    }
+
+    // "12" for lines of the main file, "lib/math.asm:12" for included ones
+    pub fn short(&self, location: Location) -> String {
+        match location.file {
+            0 => location.line.to_string(),
+            file => format!("{}:{}", self.files[file].name, location.line),
+        }
+    }
+
+    // the location along with the chain of includes leading to it, for error messages
+    pub fn describe(&self, location: Location) -> String {
+        let mut description = self.describe_line(location);
+        let mut file = &self.files[location.file];
+        while let Some(from) = file.included_from {
+            description += &format!(", included from {}", self.describe_line(from));
+            file = &self.files[from.file];
+        }
+        description
+    }
+
+    fn describe_line(&self, location: Location) -> String {
+        match location.file {
+            0 => format!("line {}", location.line),
+            file => format!("line {} of {}", location.line, self.files[file].name),
+        }
+    }
+
+    // an error at a location, which the message starts with
+    pub fn error(&self, location: Location, message: impl Display) -> Diagnostic {
+        let file = &self.files[location.file].name;
+        Diagnostic::new(format!("ERR: {}: {message}", self.describe(location)))
+            .at(file, location.line)
+    }
+
+    // an error in a line, which the message starts with along with the macros it is in
+    pub fn line_error(&self, line: &SourceLine, message: impl Display) -> Diagnostic {
+        let file = &self.files[line.location.file].name;
+        Diagnostic::new(format!("ERR: {}: {message}", self.location(line)))
+            .at(file, line.location.line)
+    }
+
+    // a warning about a line, which the message starts with like line_error
+    pub fn line_warning(&self, line: &SourceLine, message: impl Display) -> Diagnostic {
+        let file = &self.files[line.location.file].name;
+        Diagnostic::new(format!("WARNING: {}: {message}", self.location(line)))
+            .at(file, line.location.line)
+    }
+
+    // where a line came from, including the macros it was expanded from
+    pub fn location(&self, line: &SourceLine) -> String {
+        let mut location = self.describe(line.location);
+        for (name, body_line) in line.expansions.iter().rev() {
+            location += &format!(", in macro {name} at {}", self.describe_line(*body_line));
+        }
+        location
+    }
+}
+
+// a line after preprocessing, with where it came from
+#[derive(Clone)]
+pub struct SourceLine {
+    pub text: String,
+    // for macro expansions this is the invocation
+    pub location: Location,
+    // for lines from macros, the macro name and body line of each expansion, innermost first
+    pub expansions: Vec<(String, Location)>,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<(Location, String)>,
+}
+
+// an .if block that has not reached its .endif
+struct Conditional {
+    start: SourceLine,
+    // whether the lines around the block are assembled
+    outer: bool,
+    // whether the current branch is assembled
+    active: bool,
+    // whether any branch so far was
+    taken: bool,
+    seen_else: bool,
+}
+
+struct Preprocessor<'a> {
+    macros: HashMap<String, Macro>,
+    // keyed by Preprocessor::key
+    constants: HashMap<String, i64>,
+    case_sensitive: bool,
+    expansions: usize,
+    sources: Sources,
+    include_paths: &'a [PathBuf],
+    // the files currently being included, outermost first
+    including: Vec<PathBuf>,
+    // files containing .once
+    once: HashSet<PathBuf>,
+}
+
+fn split_args(args: &str) -> Vec<String> {
+    if args.trim().is_empty() {
+        return vec![];
+    }
+    args.split(',').map(|arg| arg.trim().to_string()).collect()
+}
+
+// replaces \param, \@ and %%label in a macro body line. Unknown parameters are left for
+// .irp blocks in the body, see unknown_parameter
+fn substitute(text: &str, args: &HashMap<String, String>, expansion: usize) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let prefix = match c {
+            '\\' if chars.peek() == Some(&'@') => {
+                chars.next();
+                result += &expansion.to_string();
+                continue;
+            }
+            '\\' => "\\",
+            '%' if chars.peek() == Some(&'%') => {
+                chars.next();
+                "%%"
+            }
+            c => {
+                result.push(c);
+                continue;
+            }
+        };
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if !(c.is_alphanumeric() || c == '_') {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        match prefix {
+            "%%" => result += &format!("{name}@{expansion}"),
+            _ => match args.get(&name.to_lowercase()) {
+                Some(arg) if !name.is_empty() => result += arg,
+                _ => result += &format!("\\{name}"),
+            },
+        }
+    }
+    result
+}
+
+// a \param left in a line once all macros and .irp blocks around it are expanded
+fn unknown_parameter(text: &str) -> Option<&str> {
+    // backslashes in strings are escapes
+    if text.trim_start().to_lowercase().starts_with(".string") {
+        return None;
+    }
+    let (_, after) = text.split_once('\\')?;
+    let end = after
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(after.len());
+    Some(&after[..end])
+}
+
+// the path in `.include "path"`
+fn parse_include(arg: &str) -> Option<&str> {
+    arg.trim()
+        .strip_prefix('"')?
+        .strip_suffix('"')
+        .filter(|path| !path.is_empty())
+}
+
+impl Preprocessor<'_> {
+    fn error(&self, line: &SourceLine, message: impl Display) -> Diagnostic {
+        self.sources.line_error(line, message)
+    }
+
+    // reads a file and preprocesses its lines, returning false if it could not be read
+    // reads the file at path, unless its text is given
+    fn include(
+        &mut self,
+        path: &Path,
+        text: Option<&str>,
+        included_from: Option<Location>,
+        depth: usize,
+        result: &mut Vec<SourceLine>,
+    ) -> Result<bool, Diagnostic> {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if self.once.contains(&canonical) {
+            return Ok(true);
+        }
+        if let (Some(from), true) = (included_from, self.including.contains(&canonical)) {
+            return Err(self.sources.error(
+                from,
+                format!("{} is already being included", path.display()),
+            ));
+        }
+        let text = match text {
+            Some(text) => text.to_string(),
+            None => match fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(_) => return Ok(false),
+            },
+        };
+        let file = self.sources.files.len();
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        let source_lines = lines
+            .iter()
+            .enumerate()
+            .map(|(i, text)| SourceLine {
+                text: text.clone(),
+                location: Location { file, line: i + 1 },
+                expansions: vec![],
+            })
+            .collect();
+        self.sources.files.push(SourceFile {
+            name: path.display().to_string(),
+            lines,
+            included_from,
+            path: canonical.clone(),
+        });
+        self.including.push(canonical);
+        self.process(source_lines, depth, result)?;
+        self.including.pop();
+        Ok(true)
+    }
+
+    // the name a constant is stored under
+    fn key(&self, name: &str) -> String {
+        match self.case_sensitive {
+            true => name.to_string(),
+            false => name.to_lowercase(),
+        }
+    }
+
+    fn value(&self, name: &str) -> Option<i64> {
+        self.constants.get(&self.key(name)).copied()
+    }
+
+    fn eval(&self, line: &SourceLine, text: &str) -> Result<i64, Diagnostic> {
+        expr::eval(text, |name| self.value(name)).map_err(|err| self.error(line, err))
+    }
+
+    fn condition(&self, line: &SourceLine, word: &str, rest: &str) -> Result<bool, Diagnostic> {
+        Ok(match word {
+            ".if" | ".elif" => self.eval(line, rest)? != 0,
+            _ => {
+                let name = rest.trim();
+                if name.is_empty() || name.contains(char::is_whitespace) {
+                    return Err(self.error(line, format!("{word} needs one name")));
+                }
+                self.value(name).is_some() == (word == ".ifdef")
+            }
+        })
+    }
+
+    // the value of an operand that only uses constants, None for anything else, which
+    // is left for the parser to report
+    fn constant(&self, line: &SourceLine, operand: &str) -> Result<Option<String>, Diagnostic> {
+        let operand = operand.trim();
+        if operand.starts_with(|c: char| c.is_ascii_digit())
+            && operand.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return Ok(None);
+        }
+        Ok(match expr::eval(operand, |name| self.value(name)) {
+            Ok(value) if (-0x8000..=0xFFFF).contains(&value) => Some((value as u16).to_string()),
+            Ok(value) => return Err(self.error(line, format!("{value} does not fit in 16 bits"))),
+            Err(_) => None,
+        })
+    }
+
+    // replaces #expr immediates and data directive values with their value
+    fn substitute_constants(&self, line: &SourceLine) -> Result<String, Diagnostic> {
+        let text = line.text.trim();
+        let (word, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        if matches!(word.to_lowercase().as_str(), ".word" | ".byte" | ".space") {
+            let args = split_args(rest)
+                .into_iter()
+                .map(|arg| Ok(self.constant(line, &arg)?.unwrap_or(arg)))
+                .collect::<Result<Vec<String>, Diagnostic>>()?;
+            return Ok(format!("{word} {}", args.join(", ")));
+        }
+        if word.eq_ignore_ascii_case(".string") {
+            return Ok(line.text.clone());
+        }
+        let Some((before, after)) = line.text.split_once('#') else {
+            return Ok(line.text.clone());
+        };
+        let (operand, rest) = match after.find(',') {
+            Some(i) => after.split_at(i),
+            None => (after, ""),
+        };
+        Ok(match self.constant(line, operand)? {
+            Some(value) => format!("{before}#{value}{rest}"),
+            None => line.text.clone(),
+        })
+    }
+
+    // the lines up to the .endr matching a .rept or .irp, which can be nested
+    fn repeated_lines(
+        &self,
+        start: &SourceLine,
+        word: &str,
+        lines: &mut impl Iterator<Item = SourceLine>,
+    ) -> Result<Vec<SourceLine>, Diagnostic> {
+        let mut body = Vec::new();
+        let mut nesting = 0;
+        for line in lines {
+            match line
+                .text
+                .split_whitespace()
+                .next()
+                .map(str::to_lowercase)
+                .as_deref()
+            {
+                Some(".rept" | ".irp") => nesting += 1,
+                Some(".endr") if nesting == 0 => return Ok(body),
+                Some(".endr") => nesting -= 1,
+                _ => {}
+            }
+            body.push(line);
+        }
+        Err(self.error(start, format!("{word} has no .endr")))
+    }
+
+    fn process(
+        &mut self,
+        lines: Vec<SourceLine>,
+        depth: usize,
+        result: &mut Vec<SourceLine>,
+    ) -> Result<(), Diagnostic> {
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        let mut lines = lines.into_iter();
+        while let Some(line) = lines.next() {
+            let text = line.text.trim();
+            let (word, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+            let word = word.to_lowercase();
+            let active = conditionals.last().is_none_or(|block| block.active);
+
+            if matches!(word.as_str(), ".if" | ".ifdef" | ".ifndef") {
+                // conditions in skipped blocks are not evaluated, they may use undefined names
+                let condition = active && self.condition(&line, &word, rest)?;
+                conditionals.push(Conditional {
+                    start: line.clone(),
+                    outer: active,
+                    active: condition,
+                    taken: condition,
+                    seen_else: false,
+                });
+            } else if matches!(word.as_str(), ".elif" | ".else" | ".endif") {
+                let Some(block) = conditionals.last_mut() else {
+                    return Err(self.error(&line, format!("{word} without .if")));
+                };
+                if word != ".endif" && block.seen_else {
+                    return Err(self.error(&line, format!("{word} after .else")));
+                }
+                match word.as_str() {
+                    ".elif" => {
+                        block.active =
+                            block.outer && !block.taken && self.condition(&line, &word, rest)?;
+                        block.taken |= block.active;
+                    }
+                    ".else" => {
+                        block.active = block.outer && !block.taken;
+                        block.taken = true;
+                        block.seen_else = true;
+                    }
+                    _ => {
+                        conditionals.pop();
+                    }
+                }
+            } else if !active {
+                continue;
+            } else if word == ".equ" {
+                let Some((name, value)) = rest.split_once(',') else {
+                    return Err(self.error(&line, "expected .equ NAME, value"));
+                };
+                let name = name.trim();
+                let value = self.eval(&line, value)?;
+                match self.value(name) {
+                    Some(old) if old != value => {
+                        return Err(self.error(&line, format!("{name} is already defined as {old}")))
+                    }
+                    _ => {
+                        self.constants.insert(self.key(name), value);
+                    }
+                }
+            } else if word == ".rept" || word == ".irp" {
+                let body = self.repeated_lines(&line, &word, &mut lines)?;
+                let (name, values) = match word.as_str() {
+                    ".rept" => {
+                        let count = self.eval(&line, rest)?;
+                        if count < 0 {
+                            return Err(
+                                self.error(&line, format!(".rept count {count} is negative"))
+                            );
+                        }
+                        if count > MAX_REPT {
+                            return Err(self.error(
+                                &line,
+                                format!(".rept count {count} is more than the limit of {MAX_REPT}"),
+                            ));
+                        }
+                        (None, vec![String::new(); count as usize])
+                    }
+                    _ => {
+                        let mut args = split_args(rest).into_iter();
+                        match args.next() {
+                            Some(name) if !name.is_empty() => {
+                                (Some(name.to_lowercase()), args.collect())
+                            }
+                            _ => return Err(self.error(&line, "expected .irp name, values...")),
+                        }
+                    }
+                };
+                let mut repeated = Vec::new();
+                for value in values {
+                    self.expansions += 1;
+                    // .rept has no parameter, so only \@ and %%label are replaced
+                    let args: HashMap<String, String> = name
+                        .iter()
+                        .map(|name| (name.clone(), value.clone()))
+                        .collect();
+                    // the lines keep their own location so listings show where they came from
+                    repeated.extend(body.iter().map(|body_line| SourceLine {
+                        text: substitute(&body_line.text, &args, self.expansions),
+                        ..body_line.clone()
+                    }));
+                }
+                self.process(repeated, depth + 1, result)?;
+            } else if word == ".endr" {
+                return Err(self.error(&line, ".endr without .rept or .irp"));
+            } else if word == ".macro" {
+                let (name, params) = rest
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((rest.trim(), ""));
+                if name.is_empty() {
+                    return Err(self.error(&line, ".macro needs a name"));
+                }
+                let mut body = Vec::new();
+                loop {
+                    let Some(body_line) = lines.next() else {
+                        return Err(self.error(&line, format!(".macro {name} has no .endm")));
+                    };
+                    match body_line
+                        .text
+                        .split_whitespace()
+                        .next()
+                        .map(str::to_lowercase)
+                        .as_deref()
+                    {
+                        Some(".endm") => break,
+                        Some(".macro") => {
+                            return Err(self.error(
+                                &body_line,
+                                format!("macros cannot be defined inside .macro {name}"),
+                            ))
+                        }
+                        _ => body.push((body_line.location, body_line.text)),
+                    }
+                }
+                let params = split_args(params)
+                    .iter()
+                    .map(|param| param.to_lowercase())
+                    .collect();
+                self.macros
+                    .insert(name.to_lowercase(), Macro { params, body });
+            } else if word == ".endm" {
+                return Err(self.error(&line, ".endm without .macro"));
+            } else if word == ".include" {
+                let Some(name) = parse_include(rest) else {
+                    return Err(self.error(&line, ".include needs a quoted path"));
+                };
+                let file = &self.sources.files[line.location.file];
+                let dirs = Path::new(&file.name)
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .into_iter()
+                    .chain(self.include_paths.iter().cloned());
+                match dirs.map(|dir| dir.join(name)).find(|path| path.is_file()) {
+                    Some(path)
+                        if self.include(&path, None, Some(line.location), depth, result)? => {}
+                    _ => {
+                        return Err(self.error(&line, format!("could not find {name:?} to include")))
+                    }
+                }
+            } else if word == ".once" {
+                let path = self.sources.files[line.location.file].path.clone();
+                self.once.insert(path);
+            } else if let Some(mac) = self.macros.get(&word) {
+                if depth >= MAX_DEPTH {
+                    return Err(self.sources.error(
+                        line.location,
+                        format!("macros nested more than {MAX_DEPTH} deep, is {word} recursive?"),
+                    ));
+                }
+                let args = split_args(rest);
+                if args.len() != mac.params.len() {
+                    return Err(self.error(
+                        &line,
+                        format!(
+                            "macro {word} expected {} arguments but received {}",
+                            mac.params.len(),
+                            args.len()
+                        ),
+                    ));
+                }
+                self.expansions += 1;
+                let args: HashMap<String, String> = mac.params.iter().cloned().zip(args).collect();
+                let expanded = mac
+                    .body
+                    .iter()
+                    .map(|(body_line, text)| {
+                        let mut expansions = vec![(word.clone(), *body_line)];
+                        expansions.extend(line.expansions.iter().cloned());
+                        SourceLine {
+                            text: substitute(text, &args, self.expansions),
+                            location: line.location,
+                            expansions,
+                        }
+                    })
+                    .collect();
+                self.process(expanded, depth + 1, result)?;
+            } else if !text.is_empty() {
+                if let Some(name) = unknown_parameter(text) {
+                    return Err(self.error(&line, format!("unknown parameter \\{name}")));
+                }
+                let text = self.substitute_constants(&line)?;
+                result.push(SourceLine { text, ..line });
+            }
+        }
+        match conditionals.first() {
+            Some(block) => Err(self.error(
+                &block.start,
+                format!("{} without .endif", block.start.text.trim()),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+// reads a file and everything it includes, expanding macros
+pub fn preprocess(
+    file_name: &str,
+    options: &Options,
+) -> Result<(Sources, Vec<SourceLine>), Diagnostic> {
+    run(file_name, None, options)
+}
+
+// as preprocess, with the text of the first file given instead of read, which
+// includes are looked up relative to as if it were at name
+pub fn preprocess_str(
+    name: &str,
+    text: &str,
+    options: &Options,
+) -> Result<(Sources, Vec<SourceLine>), Diagnostic> {
+    run(name, Some(text), options)
+}
+
+fn run(
+    file_name: &str,
+    text: Option<&str>,
+    options: &Options,
+) -> Result<(Sources, Vec<SourceLine>), Diagnostic> {
+    let mut preprocessor = Preprocessor {
+        macros: HashMap::new(),
+        constants: HashMap::new(),
+        case_sensitive: options.case_sensitive,
+        expansions: 0,
+        sources: Sources::default(),
+        include_paths: &options.include_paths,
+        including: vec![],
+        once: HashSet::new(),
+    };
+    for (name, value) in &options.defines {
+        preprocessor
+            .constants
+            .insert(preprocessor.key(name), *value);
+    }
+    let mut result = Vec::new();
+    if !preprocessor.include(Path::new(file_name), text, None, 0, &mut result)? {
+        return Err(Diagnostic::new("CLI ERR: could not open file"));
+    }
+    Ok((preprocessor.sources, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(source: &str) -> Result<Vec<String>, String> {
+        preprocess_str("test.asm", source, &Options::default())
+            .map(|(_, lines)| lines.into_iter().map(|line| line.text).collect())
+            .map_err(|err| err.message)
+    }
+
+    #[test]
+    fn rept_count() {
+        assert_eq!(lines(".rept 2\n  INC R1\n.endr\n").unwrap().len(), 2);
+        assert_eq!(
+            lines(".rept 0x7fffffff\n  INC R1\n.endr\n"),
+            Err(format!(
+                "ERR: line 1: .rept count 2147483647 is more than the limit of {MAX_REPT}"
+            ))
+        );
+        assert_eq!(
+            lines(".rept -1\n.endr\n"),
+            Err("ERR: line 1: .rept count -1 is negative".to_string())
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use crate::{
+    preprocess::{Location, Sources},
+    program::Program,
+    sim::{Machine, Status},
+    types::Address,
+};
+
+pub struct Profile {
+    pub status: Status,
+    pub instructions: u64,
+    // executions of each instruction address
+    pub counts: Vec<u64>,
+}
+
+pub fn profile(program: &Program, max_steps: u64) -> Profile {
+    let mut counts = vec![0; program.instructions.len()];
+    let mut machine = Machine::load(program);
+    let instructions = machine.run(program, max_steps, |address, _| {
+        counts[address as usize] += 1;
+    });
+    Profile {
+        status: machine.status,
+        instructions,
+        counts,
+    }
+}
+
+impl Profile {
+    fn per_line(&self, program: &Program) -> HashMap<Location, u64> {
+        let mut lines = HashMap::new();
+        for (address, count) in self.counts.iter().enumerate() {
+            *lines.entry(program.lines[address]).or_default() += count;
+        }
+        lines
+    }
+
+    fn per_label<'a>(&self, program: &'a Program) -> HashMap<Option<&'a str>, u64> {
+        let mut labels = HashMap::new();
+        for (address, count) in self.counts.iter().enumerate() {
+            *labels
+                .entry(program.label_of(Address(address as u16)))
+                .or_default() += count;
+        }
+        labels
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        count as f64 * 100.0 / self.instructions.max(1) as f64
+    }
+
+    pub fn print(&self, sources: &Sources, program: &Program) {
+        let status = match self.status {
+            Status::Halted => "halted",
+            Status::OutOfBounds => "ran past the last instruction",
+            Status::Running => "stopped at the step limit",
+        };
+        println!("{} instructions executed ({status})", self.instructions);
+
+        println!("\nhottest lines:");
+        let per_line = self.per_line(program);
+        let mut lines: Vec<_> = per_line.iter().filter(|(_, count)| **count > 0).collect();
+        lines.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (line, count) in lines {
+            println!(
+                "  {count:>12} {:>6.2}% {:>5} | {}",
+                self.percent(*count),
+                sources.short(*line),
+                sources.text(*line).trim()
+            );
+        }
+
+        println!("\nhottest labels:");
+        let mut labels: Vec<_> = self.per_label(program).into_iter().collect();
+        labels.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (label, count) in labels {
+            println!(
+                "  {count:>12} {:>6.2}% {}",
+                self.percent(count),
+                label.unwrap_or("<start>")
+            );
+        }
+
+        println!("\nannotated source:");
+        for (file, source) in sources.files.iter().enumerate() {
+            if file > 0 {
+                println!("\n  {:18} | ; {}", "", source.name);
+            }
+            for (i, text) in source.lines.iter().enumerate() {
+                let line = i + 1;
+                let count = match per_line.get(&Location { file, line }) {
+                    Some(count) => count.to_string(),
+                    None => String::new(),
+                };
+                println!("  {count:>12} {line:>5} | {text}");
+            }
+        }
+    }
+}
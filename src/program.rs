@@ -0,0 +1,326 @@
+use std::{collections::HashMap, ops::Range, path::PathBuf};
+
+use crate::{
+    instruction::Op,
+    opcodes::Opcodes,
+    optimize,
+    preprocess::{Location, Sources},
+    sim::DATA_MEM_SIZE,
+    stack,
+    types::{Address, ADDRESS_SPACE},
+    Diagnostic,
+};
+
+#[derive(Clone, Default)]
+pub struct Options {
+    // load immediates with the fewest instructions, see optimize::immediates
+    pub optimize_immediates: bool,
+    // reject pseudo instructions, for teaching the raw ISA
+    pub no_pseudo: bool,
+    // labels and constants that differ only in case are different names
+    pub case_sensitive: bool,
+    // searched in order for .include files not found next to the including file
+    pub include_paths: Vec<PathBuf>,
+    // constants defined on the command line, see preprocess
+    pub defines: Vec<(String, i64)>,
+    // instruction placed in gaps left by .org and .align, HALT if not given
+    pub fill: Option<Op>,
+    // words of instruction memory, the full 11 bit address space if not given
+    pub mem_size: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Section {
+    Text,
+    Data,
+}
+
+// an assembled program, with pseudo instructions unpacked and labels resolved
+pub struct Program {
+    pub instructions: Vec<Op>,
+    // labels from the source
+    pub labels: HashMap<String, Address>,
+    // return points added by CALL, see stack
+    return_labels: HashMap<String, Address>,
+    // source files, indexed by the file of each location
+    pub files: Vec<String>,
+    // source line each instruction came from
+    pub lines: Vec<Location>,
+    // initial contents of data memory from the .data section, starting at address 0
+    pub data: Vec<u16>,
+    pub data_labels: HashMap<String, u16>,
+    // source line each data word came from
+    pub data_lines: Vec<Location>,
+    // fill instructions placed by .org and .align, which are not part of the source
+    padding: Vec<Range<usize>>,
+    // words of instruction memory the program was assembled for
+    pub mem_size: usize,
+    // where the code starts, the origin of the linker script's ROM region
+    pub origin: usize,
+    // warnings about the source, which the command line prints
+    pub warnings: Vec<Diagnostic>,
+}
+
+// the data section of a program, with the instructions left for the text section
+struct Layout {
+    instructions: Vec<(Location, Op)>,
+    data: Vec<u16>,
+    data_labels: HashMap<String, u16>,
+    data_lines: Vec<Location>,
+}
+
+// the address .org or .align moves to from the current one, line is where the directive is
+fn target(
+    op: &Op,
+    current: usize,
+    section: Section,
+    size: usize,
+    sources: &Sources,
+    line: Location,
+) -> Result<usize, Diagnostic> {
+    let (directive, target) = match *op {
+        Op::Org(address) => (".org", address as usize),
+        Op::Align(size) => (".align", current.next_multiple_of(size as usize)),
+        _ => unreachable!(),
+    };
+    let contents = match section {
+        Section::Text => "code",
+        Section::Data => "data",
+    };
+    if target < current {
+        return Err(sources.error(
+            line,
+            format!(
+                ".org {target} overlaps the {contents} already placed before address {current}"
+            ),
+        ));
+    }
+    if target > size {
+        return Err(sources.error(
+            line,
+            format!("{directive} moves to {target}, past the end of the {size} word memory for {contents}"),
+        ));
+    }
+    Ok(target)
+}
+
+// places .data words and labels in data memory, and resolves immediate loads of them
+fn layout(instructions: Vec<(Location, Op)>, sources: &Sources) -> Result<Layout, Diagnostic> {
+    let mut layout = Layout {
+        instructions: Vec::new(),
+        data: Vec::new(),
+        data_labels: HashMap::new(),
+        data_lines: Vec::new(),
+    };
+    let mut defined: HashMap<String, Location> = HashMap::new();
+    let mut section = Section::Text;
+    for (line, op) in instructions {
+        match (section, op) {
+            (_, Op::Section(new)) => section = new,
+            (Section::Data, Op::Label(label)) => {
+                if let Some(first) = defined.insert(label.clone(), line) {
+                    let first = sources.describe(first);
+                    return Err(sources.error(
+                        line,
+                        format!("data label {label:?} is already defined at {first}"),
+                    ));
+                }
+                layout.data_labels.insert(label, layout.data.len() as u16);
+            }
+            (Section::Data, op @ (Op::Org(_) | Op::Align(_))) => {
+                let current = layout.data.len();
+                let target = target(&op, current, Section::Data, DATA_MEM_SIZE, sources, line)?;
+                let gap = target - current;
+                layout.data_lines.extend(vec![line; gap]);
+                layout.data.extend(vec![0; gap]);
+            }
+            (_, Op::Words(words)) => {
+                layout.data_lines.extend(words.iter().map(|_| line));
+                layout.data.extend(words);
+            }
+            (_, op) => layout.instructions.push((line, op)),
+        }
+    }
+    if layout.data.len() > DATA_MEM_SIZE {
+        // the first word that does not fit
+        return Err(sources.error(
+            layout.data_lines[DATA_MEM_SIZE],
+            format!(
+                "the .data section takes {} words, more than the {DATA_MEM_SIZE} in data memory",
+                layout.data.len()
+            ),
+        ));
+    }
+    for (line, op) in &mut layout.instructions {
+        if let Op::LoadLabel(reg, label) = op {
+            let Some(&address) = layout.data_labels.get(label) else {
+                return Err(sources.error(*line, format!("#{label} is not a data label")));
+            };
+            let [upper, lower] = address.to_be_bytes();
+            *op = Op::LoadByte(*reg, upper, lower);
+        }
+    }
+    Ok(layout)
+}
+
+impl Program {
+    // lays out the instructions of a whole program, read from sources
+    pub fn new(
+        sources: &Sources,
+        instructions: Vec<(Location, Op)>,
+        options: &Options,
+    ) -> Result<Self, Diagnostic> {
+        let Layout {
+            mut instructions,
+            data,
+            data_labels,
+            data_lines,
+        } = layout(stack::expand(instructions, sources)?, sources)?;
+        if options.optimize_immediates {
+            instructions = optimize::immediates(instructions);
+        }
+        let fill = options.fill.clone().unwrap_or(Op::HALT);
+        let mem_size = options.mem_size.unwrap_or(ADDRESS_SPACE);
+        if mem_size > ADDRESS_SPACE {
+            return Err(Diagnostic::new(format!("ERR: instruction memory can be at most {ADDRESS_SPACE} words, the most an 11 bit address reaches")));
+        }
+        let mut labels: HashMap<String, Address> = HashMap::new();
+        // where each code label is defined, for errors
+        let mut label_lines: HashMap<String, Location> = HashMap::new();
+        let mut return_labels: HashMap<String, Address> = HashMap::new();
+        let mut final_instructions = Vec::new();
+        let mut lines = Vec::new();
+        let mut padding = Vec::new();
+        for (line, instruction) in instructions
+            .into_iter()
+            .flat_map(|(line, op)| op.unpack().into_iter().map(move |op| (line, op)))
+        {
+            match instruction {
+                Op::Label(label) => {
+                    let address = Address(final_instructions.len() as u16);
+                    if stack::is_return_label(&label) {
+                        return_labels.insert(label, address);
+                        continue;
+                    }
+                    label_lines.insert(label.clone(), line);
+                    labels.insert(label, address);
+                }
+                Op::Org(_) | Op::Align(_) => {
+                    let start = final_instructions.len();
+                    let end = target(&instruction, start, Section::Text, mem_size, sources, line)?;
+                    final_instructions.extend(vec![fill.clone(); end - start]);
+                    lines.extend(vec![line; end - start]);
+                    padding.push(start..end);
+                }
+                instruction => {
+                    final_instructions.push(instruction);
+                    lines.push(line);
+                }
+            }
+        }
+        if final_instructions.len() > mem_size {
+            // the first instruction that does not fit
+            return Err(sources.error(
+                lines[mem_size],
+                format!(
+                    "the program takes {} instructions once pseudo instructions are expanded, \
+                     more than the {mem_size} words of instruction memory",
+                    final_instructions.len()
+                ),
+            ));
+        }
+        if let Some((label, line)) = label_lines
+            .iter()
+            .filter(|(label, _)| data_labels.contains_key(*label))
+            .min_by_key(|(_, line)| **line)
+        {
+            return Err(sources.error(*line, format!("{label:?} is both a code and a data label")));
+        }
+        for (instruction, line) in final_instructions.iter().zip(&lines) {
+            if let Op::LOADBR(label) = instruction {
+                if !labels.contains_key(label) && !return_labels.contains_key(label) {
+                    return Err(
+                        sources.error(*line, format!("LOADBR to undefined label {label:?}"))
+                    );
+                }
+            }
+        }
+        Ok(Self {
+            instructions: final_instructions,
+            labels,
+            return_labels,
+            files: sources.files.iter().map(|file| file.name.clone()).collect(),
+            lines,
+            data,
+            data_labels,
+            data_lines,
+            padding,
+            mem_size,
+            origin: 0,
+            warnings: Vec::new(),
+        })
+    }
+
+    // each instruction as the 16 bit word the processor decodes, see Op::encode
+    pub fn encode(&self, opcodes: &Opcodes) -> Vec<u16> {
+        self.instructions
+            .iter()
+            .map(|instruction| instruction.encode(self, opcodes))
+            .collect()
+    }
+
+    // the address LOADBR of a label branches to, including the return points of CALL
+    pub fn label_address(&self, label: &str) -> Address {
+        self.labels
+            .get(label)
+            .or_else(|| self.return_labels.get(label))
+            .copied()
+            .unwrap_or_else(|| panic!("ERR: LOADBR to undefined label {label:?}"))
+    }
+
+    // a one line summary of how much memory the program uses, from its origin on
+    pub fn size_summary(&self) -> String {
+        let used = self.instructions.len().saturating_sub(self.origin);
+        let size = self.mem_size - self.origin;
+        let padding: usize = self
+            .padding
+            .iter()
+            .map(|range| range.end.saturating_sub(range.start.max(self.origin)))
+            .sum();
+        let mut summary = format!(
+            "{used}/{size} instruction words ({:.1}%)",
+            used as f64 * 100.0 / size as f64
+        );
+        if padding > 0 {
+            summary += &format!(", {padding} of them fill");
+        }
+        if !self.data.is_empty() {
+            summary += &format!(", {} data words", self.data.len());
+        }
+        summary
+    }
+
+    // whether the instruction was placed by .org or .align to fill a gap
+    pub fn is_padding(&self, address: usize) -> bool {
+        self.padding.iter().any(|range| range.contains(&address))
+    }
+
+    // first instruction at or after the given source line, in the same file
+    pub fn address_of_line(&self, line: Location) -> Option<Address> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(_, &l)| l.file == line.file && l >= line)
+            .min_by_key(|(i, &l)| (l, *i))
+            .map(|(i, _)| Address(i as u16))
+    }
+
+    // the label an address falls under, if any
+    pub fn label_of(&self, address: Address) -> Option<&str> {
+        self.labels
+            .iter()
+            .filter(|(_, a)| a.0 <= address.0)
+            .max_by_key(|(name, a)| (a.0, *name))
+            .map(|(name, _)| name.as_str())
+    }
+}
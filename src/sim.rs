@@ -0,0 +1,203 @@
+// Execution model of the MINI-RISC core, used to run assembled programs without a
+// verilog simulator. Registers and data memory are 16 bits wide, MUL and DIV write
+// their result to R0, and CMP only touches the CMP (greater than) and EQ flags.
+use crate::{
+    instruction::Op,
+    program::Program,
+    types::{BitPos, Reg, CARRY, COMPARE, EQUAL, IO, NEGATIVE, OVERFLOW, PARITY, ZERO},
+};
+
+pub const DATA_MEM_SIZE: usize = 1 << 16;
+// limit for tools that run a program to completion, in case it never halts
+pub const DEFAULT_MAX_STEPS: u64 = 10_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    Halted,
+    // the program counter ran past the end of the program
+    OutOfBounds,
+}
+
+#[derive(Clone)]
+pub struct Machine {
+    pub regs: [u16; 8],
+    pub flags: u8,
+    pub pc: u16,
+    pub branch: u16,
+    pub mem: Vec<u16>,
+    pub in_port: u16,
+    pub out_port: u16,
+    pub status: Status,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Self {
+            regs: [0; 8],
+            flags: 0,
+            pc: 0,
+            branch: 0,
+            mem: vec![0; DATA_MEM_SIZE],
+            in_port: 0,
+            out_port: 0,
+            status: Status::Running,
+        }
+    }
+
+    // a machine with data memory initialized from the program's .data section
+    pub fn load(program: &Program) -> Self {
+        let mut machine = Self::new();
+        machine.mem[..program.data.len()].copy_from_slice(&program.data);
+        machine
+    }
+
+    pub fn reg(&self, reg: Reg) -> u16 {
+        self.regs[reg.0 as usize]
+    }
+
+    fn set_reg(&mut self, reg: Reg, value: u16) {
+        self.regs[reg.0 as usize] = value;
+    }
+
+    pub fn flag(&self, pos: BitPos) -> bool {
+        self.flags & (1 << pos.0) != 0
+    }
+
+    fn set_flag(&mut self, pos: BitPos, value: bool) {
+        if value {
+            self.flags |= 1 << pos.0;
+        } else {
+            self.flags &= !(1 << pos.0);
+        }
+    }
+
+    // Z, N and P are updated by every ALU op that writes a register
+    fn set_result_flags(&mut self, result: u16) {
+        self.set_flag(ZERO, result == 0);
+        self.set_flag(NEGATIVE, result & 0x8000 != 0);
+        self.set_flag(PARITY, result.count_ones() % 2 == 1);
+    }
+
+    fn add(&mut self, out: Reg, a: u16, b: u16) {
+        let (result, carry) = a.overflowing_add(b);
+        let (_, overflow) = (a as i16).overflowing_add(b as i16);
+        self.set_flag(CARRY, carry);
+        self.set_flag(OVERFLOW, overflow);
+        self.set_result_flags(result);
+        self.set_reg(out, result);
+    }
+
+    fn logic(&mut self, out: Reg, result: u16) {
+        self.set_result_flags(result);
+        self.set_reg(out, result);
+    }
+
+    pub fn step(&mut self, program: &Program) -> Status {
+        if self.status != Status::Running {
+            return self.status;
+        }
+        let Some(op) = program.instructions.get(self.pc as usize) else {
+            self.status = Status::OutOfBounds;
+            return self.status;
+        };
+        self.pc += 1;
+        match *op {
+            Op::ADD(out, in1, in2) => self.add(out, self.reg(in1), self.reg(in2)),
+            Op::SUB(out, in1, in2) => {
+                let (a, b) = (self.reg(in1), self.reg(in2));
+                let (result, borrow) = a.overflowing_sub(b);
+                let (_, overflow) = (a as i16).overflowing_sub(b as i16);
+                self.set_flag(CARRY, borrow);
+                self.set_flag(OVERFLOW, overflow);
+                self.set_result_flags(result);
+                self.set_reg(out, result);
+            }
+            Op::MUL(in1, in2) => {
+                let product = self.reg(in1) as u32 * self.reg(in2) as u32;
+                self.set_flag(CARRY, product > 0xFFFF);
+                self.set_flag(OVERFLOW, product > 0xFFFF);
+                self.logic(Reg(0), product as u16);
+            }
+            Op::DIV(in1, in2) => {
+                let quotient = self.reg(in1).checked_div(self.reg(in2));
+                self.set_flag(OVERFLOW, quotient.is_none());
+                if let Some(quotient) = quotient {
+                    self.logic(Reg(0), quotient);
+                }
+            }
+            Op::NOT(out, in1) => self.logic(out, !self.reg(in1)),
+            Op::AND(out, in1, in2) => self.logic(out, self.reg(in1) & self.reg(in2)),
+            Op::OR(out, in1, in2) => self.logic(out, self.reg(in1) | self.reg(in2)),
+            Op::XOR(out, in1, in2) => self.logic(out, self.reg(in1) ^ self.reg(in2)),
+            Op::INC(out, in1) => self.add(out, self.reg(in1), 1),
+            Op::CMP(in1, in2) => {
+                let (a, b) = (self.reg(in1), self.reg(in2));
+                self.set_flag(EQUAL, a == b);
+                self.set_flag(COMPARE, a > b);
+            }
+            Op::RR(out, in1) => {
+                let value = self.reg(in1);
+                self.set_flag(CARRY, value & 1 != 0);
+                self.logic(out, value.rotate_right(1));
+            }
+            Op::RL(out, in1) => {
+                let value = self.reg(in1);
+                self.set_flag(CARRY, value & 0x8000 != 0);
+                self.logic(out, value.rotate_left(1));
+            }
+            Op::SETB(reg, pos) => self.set_reg(reg, self.reg(reg) | 1 << pos.0),
+            Op::CLRB(reg, pos) => self.set_reg(reg, self.reg(reg) & !(1 << pos.0)),
+            Op::CPLB(reg, pos) => self.set_reg(reg, self.reg(reg) ^ 1 << pos.0),
+            Op::SETF(pos) => self.set_flag(pos, true),
+            Op::CLRF(pos) => self.set_flag(pos, false),
+            Op::CPLF(pos) => self.set_flag(pos, !self.flag(pos)),
+            Op::LOADBR(ref label) => self.branch = program.label_address(label).0,
+            Op::JF(pos) => {
+                if self.flag(pos) {
+                    self.pc = self.branch;
+                }
+            }
+            Op::LOAD(dest, addr) => self.set_reg(dest, self.mem[self.reg(addr) as usize]),
+            Op::STORE(addr, source) => {
+                let addr = self.reg(addr) as usize;
+                self.mem[addr] = self.reg(source);
+            }
+            Op::LBL(reg, byte) => self.set_reg(reg, self.reg(reg) & 0xFF00 | byte as u16),
+            Op::LBH(reg, byte) => self.set_reg(reg, self.reg(reg) & 0x00FF | (byte as u16) << 8),
+            Op::MOV(dest, source) => self.set_reg(dest, self.reg(source)),
+            Op::MOVOUT(reg) => self.out_port = self.reg(reg),
+            Op::MOVIN(reg) => self.set_reg(reg, self.in_port),
+            Op::MOVB(pos) => self.set_flag(IO, self.in_port & 1 << pos.0 != 0),
+            Op::HALT => self.status = Status::Halted,
+            _ => panic!("cannot execute pseudo instruction {op:?}"),
+        }
+        self.status
+    }
+
+    // runs until the program stops or max_steps instructions have executed, calling
+    // on_step with the address of each instruction after it executes
+    pub fn run(
+        &mut self,
+        program: &Program,
+        max_steps: u64,
+        mut on_step: impl FnMut(u16, &Machine),
+    ) -> u64 {
+        let mut steps = 0;
+        while self.status == Status::Running && steps < max_steps {
+            let address = self.pc;
+            if self.step(program) == Status::OutOfBounds {
+                break;
+            }
+            steps += 1;
+            on_step(address, self);
+        }
+        steps
+    }
+}
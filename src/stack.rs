@@ -0,0 +1,276 @@
+// Software stack for PUSH, POP, CALL and RET. The stack pointer always holds the
+// next free word, and the stack grows up or down from its base address.
+//
+// LOADBR only takes a label, so RET cannot jump to an address popped off the stack.
+// Instead CALL pushes a token naming its call site, 0xFFFF - n for the nth CALL, and
+// RET increments the popped token once per call site, jumping to the return point
+// of the site whose count brings it to zero.
+use crate::{
+    instruction::Op,
+    preprocess::{Location, Sources},
+    types::{Reg, ZERO},
+    Diagnostic,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Stack {
+    pub sp: Reg,
+    // holds the return token in CALL and RET
+    pub scratch: Reg,
+    pub direction: Direction,
+}
+
+fn return_label(site: u16) -> String {
+    // labels in the source can never contain ':'
+    format!("ret:{site}")
+}
+
+// whether a label is a return point added by CALL rather than one from the source
+pub fn is_return_label(label: &str) -> bool {
+    label.starts_with("ret:")
+}
+
+impl Stack {
+    fn grow(&self) -> Vec<Op> {
+        match self.direction {
+            Direction::Up => vec![Op::INC(self.sp, self.sp)],
+            Direction::Down => vec![Op::Dec(self.sp)],
+        }
+    }
+
+    fn shrink(&self) -> Vec<Op> {
+        match self.direction {
+            Direction::Up => vec![Op::Dec(self.sp)],
+            Direction::Down => vec![Op::INC(self.sp, self.sp)],
+        }
+    }
+
+    fn push(&self, reg: Reg) -> Vec<Op> {
+        let mut ops = vec![Op::STORE(self.sp, reg)];
+        ops.extend(self.grow());
+        ops
+    }
+
+    fn pop(&self, reg: Reg) -> Vec<Op> {
+        let mut ops = self.shrink();
+        ops.push(Op::LOAD(reg, self.sp));
+        ops
+    }
+
+    fn call(&self, label: String, site: u16) -> Vec<Op> {
+        let token = 0xFFFF - site;
+        let mut ops = vec![Op::LoadByte(self.scratch, (token >> 8) as u8, token as u8)];
+        ops.extend(self.push(self.scratch));
+        ops.extend([Op::Jmp(label), Op::Label(return_label(site))]);
+        ops
+    }
+
+    fn ret(&self, sites: u16) -> Vec<Op> {
+        let mut ops = self.pop(self.scratch);
+        for site in 0..sites {
+            ops.extend([
+                Op::INC(self.scratch, self.scratch),
+                Op::LOADBR(return_label(site)),
+                Op::JF(ZERO),
+            ]);
+        }
+        // the token matched no call site, the stack is corrupt
+        ops.push(Op::HALT);
+        ops
+    }
+}
+
+// replaces .stack, PUSH, POP, CALL and RET with the instructions implementing them
+pub fn expand(
+    instructions: Vec<(Location, Op)>,
+    sources: &Sources,
+) -> Result<Vec<(Location, Op)>, Diagnostic> {
+    let mut stacks = instructions.iter().filter_map(|(line, op)| match op {
+        Op::Stack(stack, _) => Some((*line, *stack)),
+        _ => None,
+    });
+    let stack = stacks.next().map(|(_, stack)| stack);
+    if let Some((line, _)) = stacks.next() {
+        return Err(sources.error(line, ".stack can only be used once"));
+    }
+    let stack = |line: Location, op: &str| {
+        stack.ok_or_else(|| sources.error(line, format!("{op} used without a .stack directive")))
+    };
+    let sites = instructions
+        .iter()
+        .filter(|(_, op)| matches!(op, Op::Call(_)))
+        .count() as u16;
+
+    let mut result = Vec::new();
+    let mut site = 0;
+    for (line, op) in instructions {
+        let ops = match op {
+            Op::Stack(stack, base) => {
+                vec![Op::LoadByte(stack.sp, (base >> 8) as u8, base as u8)]
+            }
+            Op::Push(reg) => stack(line, "PUSH")?.push(reg),
+            Op::Pop(reg) => stack(line, "POP")?.pop(reg),
+            Op::Call(label) => {
+                site += 1;
+                stack(line, "CALL")?.call(label, site - 1)
+            }
+            Op::Ret => stack(line, "RET")?.ret(sites),
+            op => vec![op],
+        };
+        result.extend(ops.into_iter().map(|op| (line, op)));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble,
+        sim::{Machine, Status},
+        Options,
+    };
+
+    const STACK: Stack = Stack {
+        sp: Reg(7),
+        scratch: Reg(0),
+        direction: Direction::Up,
+    };
+
+    // the expansion, as text since Op has no PartialEq
+    fn expanded(ops: Vec<Op>) -> Result<String, String> {
+        let at = Location { file: 0, line: 1 };
+        let ops = expand(
+            ops.into_iter().map(|op| (at, op)).collect(),
+            &Sources::from_names(&["test.asm".to_string()]),
+        )
+        .map_err(|err| err.message)?;
+        Ok(format!(
+            "{:?}",
+            ops.into_iter().map(|(_, op)| op).collect::<Vec<_>>()
+        ))
+    }
+
+    fn ops(ops: Vec<Op>) -> Result<String, String> {
+        Ok(format!("{ops:?}"))
+    }
+
+    #[test]
+    fn call_and_ret() {
+        let (sp, scratch) = (STACK.sp, STACK.scratch);
+        assert_eq!(
+            expanded(vec![
+                Op::Stack(STACK, 0x1234),
+                Op::Call("first".to_string()),
+                Op::Call("second".to_string()),
+                Op::Ret,
+            ]),
+            ops(vec![
+                Op::LoadByte(sp, 0x12, 0x34),
+                // the token of the first site is 0xFFFF, the second 0xFFFE
+                Op::LoadByte(scratch, 0xFF, 0xFF),
+                Op::STORE(sp, scratch),
+                Op::INC(sp, sp),
+                Op::Jmp("first".to_string()),
+                Op::Label("ret:0".to_string()),
+                Op::LoadByte(scratch, 0xFF, 0xFE),
+                Op::STORE(sp, scratch),
+                Op::INC(sp, sp),
+                Op::Jmp("second".to_string()),
+                Op::Label("ret:1".to_string()),
+                // each increment brings one of the tokens to zero
+                Op::Dec(sp),
+                Op::LOAD(scratch, sp),
+                Op::INC(scratch, scratch),
+                Op::LOADBR("ret:0".to_string()),
+                Op::JF(ZERO),
+                Op::INC(scratch, scratch),
+                Op::LOADBR("ret:1".to_string()),
+                Op::JF(ZERO),
+                Op::HALT,
+            ])
+        );
+    }
+
+    #[test]
+    fn nested_calls_return_to_their_sites() {
+        let source = "
+            .stack R7, 100
+            main:
+              CALL outer
+              INC R4, R4
+              HALT
+            outer:
+              MOV R2, #1
+              CALL inner
+              INC R3, R3
+              RET
+            inner:
+              MOV R5, #7
+              RET
+        ";
+        let program = assemble(source, &Options::default()).unwrap();
+        let mut machine = Machine::load(&program);
+        machine.run(&program, 1000, |_, _| {});
+        assert_eq!(machine.status, Status::Halted);
+        assert_eq!(machine.regs[2..6], [1, 1, 1, 7]);
+        // both return tokens were popped
+        assert_eq!(machine.regs[7], 100);
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let down = Stack {
+            direction: Direction::Down,
+            ..STACK
+        };
+        let (sp, r1) = (STACK.sp, Reg(1));
+        assert_eq!(
+            expanded(vec![Op::Stack(STACK, 0), Op::Push(r1), Op::Pop(r1)]),
+            ops(vec![
+                Op::LoadByte(sp, 0, 0),
+                Op::STORE(sp, r1),
+                Op::INC(sp, sp),
+                Op::Dec(sp),
+                Op::LOAD(r1, sp),
+            ])
+        );
+        assert_eq!(
+            expanded(vec![Op::Stack(down, 0), Op::Push(r1), Op::Pop(r1)]),
+            ops(vec![
+                Op::LoadByte(sp, 0, 0),
+                Op::STORE(sp, r1),
+                Op::Dec(sp),
+                Op::INC(sp, sp),
+                Op::LOAD(r1, sp),
+            ])
+        );
+    }
+
+    #[test]
+    fn stack_is_required() {
+        for (op, name) in [
+            (Op::Push(Reg(1)), "PUSH"),
+            (Op::Pop(Reg(1)), "POP"),
+            (Op::Call("sub".to_string()), "CALL"),
+            (Op::Ret, "RET"),
+        ] {
+            assert_eq!(
+                expanded(vec![op]),
+                Err(format!(
+                    "ERR: line 1: {name} used without a .stack directive"
+                ))
+            );
+        }
+        assert_eq!(
+            expanded(vec![Op::Stack(STACK, 0), Op::Stack(STACK, 0)]),
+            Err("ERR: line 1: .stack can only be used once".to_string())
+        );
+    }
+}
@@ -0,0 +1,72 @@
+// Self checking testbench tasks. Like the program task, these refer to signals of
+// the enclosing testbench directly: clk, reset, halt, in_port, out_port, registers
+// and data_mem.
+use std::collections::BTreeSet;
+
+use crate::{
+    instruction::Op,
+    program::Program,
+    sim::{Machine, Status},
+};
+
+pub fn to_testbench(name: &str, program: &Program, max_steps: u64) -> String {
+    let mut machine = Machine::load(program);
+    let mut stored = BTreeSet::new();
+    machine.run(program, max_steps, |address, machine| {
+        if let Op::STORE(addr, _) = program.instructions[address as usize] {
+            stored.insert(machine.reg(addr));
+        }
+    });
+    match machine.status {
+        Status::Halted => {}
+        Status::OutOfBounds => panic!("TESTBENCH ERR: program ran past the last instruction"),
+        Status::Running => {
+            panic!("TESTBENCH ERR: program did not halt within {max_steps} instructions")
+        }
+    }
+
+    let check = |signal: String, expected: u16| {
+        format!(
+            "  if ({signal} !== 16'd{expected}) begin\n    \
+             $display(\"FAIL: {signal} = %0d, expected {expected}\", {signal});\n    \
+             errors = errors + 1;\n  end"
+        )
+    };
+    let mut result: Vec<String> = vec![
+        format!("task check_{name};"),
+        "integer errors;".to_string(),
+        "begin".to_string(),
+        "  errors = 0;".to_string(),
+    ];
+    for (i, value) in machine.regs.iter().enumerate() {
+        result.push(check(format!("registers[{i}]"), *value));
+    }
+    for addr in stored {
+        result.push(check(
+            format!("data_mem[{addr}]"),
+            machine.mem[addr as usize],
+        ));
+    }
+    result.push(check("out_port".to_string(), machine.out_port));
+    result.extend([
+        "  if (errors == 0)".to_string(),
+        format!("    $display(\"{name}: PASS\");"),
+        "  else".to_string(),
+        format!("    $display(\"{name}: FAIL (%0d errors)\", errors);"),
+        "end".to_string(),
+        "endtask".to_string(),
+        String::new(),
+        format!("task run_{name};"),
+        "begin".to_string(),
+        format!("  {name};"),
+        "  in_port = 16'd0;".to_string(),
+        "  reset = 1;".to_string(),
+        "  @(posedge clk);".to_string(),
+        "  reset = 0;".to_string(),
+        "  wait (halt);".to_string(),
+        format!("  check_{name};"),
+        "end".to_string(),
+        "endtask".to_string(),
+    ]);
+    result.join("\n")
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use crate::{
+    program::Program,
+    sim::{Machine, Status},
+    types::Address,
+};
+
+// cycles per instruction for the core, overridable with a timing table file
+const DEFAULT_CYCLES: [(&str, u64); 29] = [
+    ("ADD", 1),
+    ("MUL", 4),
+    ("SUB", 1),
+    ("DIV", 8),
+    ("NOT", 1),
+    ("AND", 1),
+    ("OR", 1),
+    ("XOR", 1),
+    ("INC", 1),
+    ("CMP", 1),
+    ("RR", 1),
+    ("RL", 1),
+    ("SETB", 1),
+    ("CLRB", 1),
+    ("CPLB", 1),
+    ("SETF", 1),
+    ("CLRF", 1),
+    ("CPLF", 1),
+    ("LOADBR", 1),
+    ("JF", 2),
+    ("LOAD", 2),
+    ("STORE", 2),
+    ("LBL", 1),
+    ("LBH", 1),
+    ("MOV", 1),
+    ("MOVOUT", 1),
+    ("MOVIN", 1),
+    ("MOVB", 1),
+    ("HALT", 1),
+];
+
+pub struct TimingTable(HashMap<String, u64>);
+
+impl Default for TimingTable {
+    fn default() -> Self {
+        Self(
+            DEFAULT_CYCLES
+                .iter()
+                .map(|(opcode, cycles)| (opcode.to_string(), *cycles))
+                .collect(),
+        )
+    }
+}
+
+impl TimingTable {
+    // reads `OPCODE cycles` lines on top of the default table, # starts a comment
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(table: &str) -> Self {
+        let mut result = Self::default();
+        for line in table.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (opcode, cycles) = line
+                .split_once(char::is_whitespace)
+                .unwrap_or_else(|| panic!("TIMING ERR: expected `OPCODE cycles`, got {line:?}"));
+            let opcode = opcode.to_uppercase();
+            if !result.0.contains_key(&opcode) {
+                panic!("TIMING ERR: unknown opcode {opcode:?}");
+            }
+            let cycles = cycles
+                .trim()
+                .parse()
+                .unwrap_or_else(|_| panic!("TIMING ERR: invalid cycle count for {opcode}"));
+            result.0.insert(opcode, cycles);
+        }
+        result
+    }
+
+    pub fn cycles(&self, opcode: &str) -> u64 {
+        self.0[opcode]
+    }
+}
+
+#[derive(Default)]
+pub struct LoopTiming {
+    pub iterations: u64,
+    pub worst: u64,
+    pub total: u64,
+}
+
+pub struct TimingReport {
+    pub status: Status,
+    pub instructions: u64,
+    pub cycles: u64,
+    // cycles spent in each label delimited block, None being code before the first label
+    pub per_label: HashMap<Option<String>, u64>,
+    // backward branch targets and the cycles taken by each pass through the loop
+    pub loops: HashMap<u16, LoopTiming>,
+}
+
+pub fn measure(program: &Program, table: &TimingTable, max_steps: u64) -> TimingReport {
+    let costs: Vec<u64> = program
+        .instructions
+        .iter()
+        .map(|op| table.cycles(&op.opcode()))
+        .collect();
+    let blocks: Vec<Option<&str>> = (0..program.instructions.len())
+        .map(|i| program.label_of(Address(i as u16)))
+        .collect();
+
+    let mut cycles = 0;
+    let mut per_label: HashMap<Option<String>, u64> = HashMap::new();
+    let mut loops: HashMap<u16, LoopTiming> = HashMap::new();
+    // cycle count when each address was last reached
+    let mut last_arrival = vec![0; program.instructions.len()];
+    let mut machine = Machine::load(program);
+    let instructions = machine.run(program, max_steps, |address, machine| {
+        let cost = costs[address as usize];
+        cycles += cost;
+        *per_label
+            .entry(blocks[address as usize].map(String::from))
+            .or_default() += cost;
+
+        let target = machine.pc;
+        if target <= address {
+            let timing = loops.entry(target).or_default();
+            let iteration = cycles - last_arrival[target as usize];
+            timing.iterations += 1;
+            timing.worst = timing.worst.max(iteration);
+            timing.total += iteration;
+        }
+        if let Some(arrival) = last_arrival.get_mut(target as usize) {
+            *arrival = cycles;
+        }
+    });
+
+    TimingReport {
+        status: machine.status,
+        instructions,
+        cycles,
+        per_label,
+        loops,
+    }
+}
+
+impl TimingReport {
+    pub fn print(&self, program: &Program) {
+        let status = match self.status {
+            Status::Halted => "halted",
+            Status::OutOfBounds => "ran past the last instruction",
+            Status::Running => "stopped at the step limit",
+        };
+        println!(
+            "{} cycles, {} instructions ({status})",
+            self.cycles, self.instructions
+        );
+
+        println!("\ncycles per label:");
+        let mut labels: Vec<_> = self.per_label.iter().collect();
+        labels.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (label, cycles) in labels {
+            let percent = *cycles as f64 * 100.0 / self.cycles.max(1) as f64;
+            let label = label.as_deref().unwrap_or("<start>");
+            println!("  {label:<20} {cycles:>12} {percent:>6.2}%");
+        }
+
+        if self.loops.is_empty() {
+            return;
+        }
+        println!("\nloops (worst case iteration):");
+        let mut loops: Vec<_> = self.loops.iter().collect();
+        loops.sort_by(|a, b| b.1.worst.cmp(&a.1.worst).then(a.0.cmp(b.0)));
+        for (target, timing) in loops {
+            let name = match program.label_of(Address(*target)) {
+                Some(label) if program.labels[label].0 == *target => label.to_string(),
+                _ => format!("pc={target}"),
+            };
+            println!(
+                "  {name:<20} worst {:>6} cycles, {} iterations, {} cycles total",
+                timing.worst, timing.iterations, timing.total
+            );
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use std::fmt::Display;
 
-#[derive(Clone, Copy, Debug)]
+use crate::Diagnostic;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Reg(pub u8);
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct BitPos(pub u8);
 // #[derive(Clone, Copy, Debug)]
 // pub struct RegBitPos(pub u8);
@@ -10,12 +12,26 @@ pub struct BitPos(pub u8);
 // pub struct FlagBitPos(pub u8);
 // #[derive(Clone, Copy, Debug)]
 // pub struct PortBitPos(pub u8);
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Address(pub u16);
+// instruction addresses are 11 bits
+pub const ADDRESS_SPACE: usize = 1 << 11;
+
+// flag register bits, indexed by their BitPos
+pub const FLAG_NAMES: [&str; 8] = ["C", "V", "CMP", "EQ", "IO", "P", "N", "Z"];
+pub const CARRY: BitPos = BitPos(0);
+pub const OVERFLOW: BitPos = BitPos(1);
+pub const COMPARE: BitPos = BitPos(2);
+pub const EQUAL: BitPos = BitPos(3);
+pub const IO: BitPos = BitPos(4);
+pub const PARITY: BitPos = BitPos(5);
+pub const NEGATIVE: BitPos = BitPos(6);
+pub const ZERO: BitPos = BitPos(7);
 
 impl Reg {
-    pub fn from_str(str: &str) -> Self {
-        match str.to_lowercase().as_ref() {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(str: &str) -> Result<Self, Diagnostic> {
+        Ok(match str.to_lowercase().as_ref() {
             "r0" => Reg(0),
             "r1" => Reg(1),
             "r2" => Reg(2),
@@ -24,8 +40,12 @@ impl Reg {
             "r5" => Reg(5),
             "r6" => Reg(6),
             "r7" => Reg(7),
-            _ => panic!("Invalid register string {str}, eg: use R0, R1, R2 ... R7"),
-        }
+            _ => {
+                return Err(Diagnostic::new(format!(
+                    "Invalid register string {str}, eg: use R0, R1, R2 ... R7"
+                )))
+            }
+        })
     }
 }
 
@@ -42,7 +62,7 @@ impl Display for Reg {
 impl Display for BitPos {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let pos = self.0;
-        if pos > 16 {
+        if pos > 15 {
             panic!("invalid bit pos: {pos}");
         };
         write!(f, "4'd{pos}")
@@ -50,8 +70,8 @@ impl Display for BitPos {
 }
 
 impl BitPos {
-    pub fn from_flag(bit: &str) -> Self {
-        match bit.to_lowercase().as_ref() {
+    pub fn from_flag(bit: &str) -> Result<Self, Diagnostic> {
+        Ok(match bit.to_lowercase().as_ref() {
             "c" | "carry" => BitPos(0),
             "v" | "overflow" => BitPos(1),
             "cmp" | "compare" => BitPos(2),
@@ -60,15 +80,15 @@ impl BitPos {
             "p" | "parity" => BitPos(5),
             "n" | "negative" => BitPos(6),
             "z" | "zero" => BitPos(7),
-            _ => panic!("invalid flag bit: {bit}"),
-        }
+            _ => return Err(Diagnostic::new(format!("invalid flag bit: {bit}"))),
+        })
     }
 }
 
 impl Display for Address {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let addr = self.0;
-        if addr > (1 << 11) {
+        if addr as usize >= ADDRESS_SPACE {
             panic!("invalid address: {addr} (addr should be 11 bits)");
         };
         write!(f, "11'd{addr}")